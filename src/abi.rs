@@ -0,0 +1,292 @@
+use crate::chainstate::ReceiptLog;
+use ethabi::{Contract, ParamType, Token};
+use ethereum_types::H160;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Decoded argument of the call or the event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: serde_json::Value,
+}
+
+/// Method called by the transaction with its arguments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedCall {
+    pub method: String,
+    pub signature: String,
+    pub args: Vec<DecodedParam>,
+}
+
+/// Event emitted by the contract with its arguments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedLog {
+    pub address: H160,
+    pub event: String,
+    pub signature: String,
+    pub args: Vec<DecodedParam>,
+}
+
+/// Value of the decoded token as JSON. Numbers are decimal strings, as they don't fit JSON numbers
+fn token_value(token: &Token, kind: &ParamType) -> serde_json::Value {
+    use serde_json::Value;
+    match (token, kind) {
+        (Token::Address(x), _) => Value::String(format!("{:?}", x)),
+        (Token::FixedBytes(x), _) | (Token::Bytes(x), _) => {
+            Value::String(format!("0x{}", hex::encode(x)))
+        }
+        (Token::Uint(x), _) => Value::String(x.to_string()),
+        (Token::Int(x), _) => {
+            // two's complement of the sign-extended value
+            if x.bit(255) {
+                Value::String(format!("-{}", (!*x).overflowing_add(1.into()).0))
+            } else {
+                Value::String(x.to_string())
+            }
+        }
+        (Token::Bool(x), _) => Value::Bool(*x),
+        (Token::String(x), _) => Value::String(x.clone()),
+        (Token::Array(items), ParamType::Array(kind))
+        | (Token::FixedArray(items), ParamType::FixedArray(kind, _)) => {
+            Value::Array(items.iter().map(|x| token_value(x, kind)).collect())
+        }
+        (Token::Tuple(items), ParamType::Tuple(kinds)) => Value::Array(
+            items
+                .iter()
+                .zip(kinds)
+                .map(|(x, kind)| token_value(x, kind))
+                .collect(),
+        ),
+        (x, _) => Value::String(x.to_string()),
+    }
+}
+
+fn decoded_params<'a>(
+    params: impl Iterator<Item = (&'a str, &'a ParamType)>,
+    tokens: &[Token],
+) -> Vec<DecodedParam> {
+    params
+        .zip(tokens)
+        .map(|((name, kind), token)| DecodedParam {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            value: token_value(token, kind),
+        })
+        .collect()
+}
+
+/// Contract ABI of the JSON file, either the plain list of entries
+/// or the build artifact of Truffle or Hardhat with `abi` field
+fn parse_contract(source: &str) -> anyhow::Result<Contract> {
+    let value: serde_json::Value = serde_json::from_str(source)?;
+    let abi = match value.get("abi") {
+        Some(x) => x.clone(),
+        None => value,
+    };
+    Ok(serde_json::from_value(abi)?)
+}
+
+/// Contract ABIs by their addresses, decoding calls of the contracts and their events
+#[derive(Debug, Clone, Default)]
+pub struct AbiRegistry {
+    contracts: HashMap<H160, Contract>,
+}
+
+impl AbiRegistry {
+    pub fn insert(&mut self, address: H160, contract: Contract) {
+        self.contracts.insert(address, contract);
+    }
+
+    pub fn len(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+
+    /// Reads ABI JSON files from the directory, named by the address of the contract,
+    /// i.e. `0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2.json`
+    pub fn from_dir(dir: &str) -> anyhow::Result<Self> {
+        let entries = fs::read_dir(Path::new(dir))
+            .map_err(|e| anyhow::anyhow!("failed to open ABI directory {}: {}", dir, e))?;
+        let mut registry = Self::default();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+            let stem = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default();
+            let address = H160::from_str(stem.trim_start_matches("0x")).map_err(|_| {
+                anyhow::anyhow!("ABI file {} is not named by the address", path.display())
+            })?;
+            let contract = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|x| parse_contract(&x))
+                .map_err(|e| anyhow::anyhow!("failed to parse ABI {}: {}", path.display(), e))?;
+            registry.insert(address, contract);
+        }
+        Ok(registry)
+    }
+
+    /// Method and arguments of the call to the contract, when its ABI is known
+    pub fn decode_call(&self, to: &H160, input: &[u8]) -> Option<DecodedCall> {
+        let contract = self.contracts.get(to)?;
+        if input.len() < 4 {
+            return None;
+        }
+        let function = contract
+            .functions()
+            .find(|x| x.short_signature() == input[..4])?;
+        let tokens = function.decode_input(&input[4..]).ok()?;
+        let inputs = function.inputs.iter().map(|x| (x.name.as_str(), &x.kind));
+        Some(DecodedCall {
+            method: function.name.clone(),
+            signature: signature(&function.name, function.inputs.iter().map(|x| &x.kind)),
+            args: decoded_params(inputs, &tokens),
+        })
+    }
+
+    /// Event and its arguments, when ABI of the emitting contract is known
+    pub fn decode_log(&self, log: &ReceiptLog) -> Option<DecodedLog> {
+        let contract = self.contracts.get(&log.address)?;
+        let topic = log.topics.first()?;
+        let event = contract
+            .events()
+            .find(|x| !x.anonymous && x.signature().as_bytes() == topic.as_bytes())?;
+        let raw = ethabi::RawLog {
+            topics: log
+                .topics
+                .iter()
+                .map(|x| ethabi::Hash::from_slice(x.as_bytes()))
+                .collect(),
+            data: log.data.to_vec(),
+        };
+        let parsed = event.parse_log(raw).ok()?;
+        let tokens: Vec<Token> = parsed.params.into_iter().map(|x| x.value).collect();
+        let inputs = event.inputs.iter().map(|x| (x.name.as_str(), &x.kind));
+        Some(DecodedLog {
+            address: log.address,
+            event: event.name.clone(),
+            signature: signature(&event.name, event.inputs.iter().map(|x| &x.kind)),
+            args: decoded_params(inputs, &tokens),
+        })
+    }
+}
+
+/// Canonical signature of the method or event, i.e. `transfer(address,uint256)`
+fn signature<'a>(name: &str, kinds: impl Iterator<Item = &'a ParamType>) -> String {
+    let kinds: Vec<String> = kinds.map(|x| x.to_string()).collect();
+    format!("{}({})", name, kinds.join(","))
+}
+
+static REGISTRY: OnceLock<AbiRegistry> = OnceLock::new();
+
+/// Sets up ABIs shared by the whole process.
+/// Returns false if they are already in use
+pub fn configure(registry: AbiRegistry) -> bool {
+    REGISTRY.set(registry).is_ok()
+}
+
+/// ABIs shared by the whole process, none unless they were configured
+pub fn shared() -> &'static AbiRegistry {
+    REGISTRY.get_or_init(AbiRegistry::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::H256;
+    use hex_literal::hex;
+
+    const WETH: [u8; 20] = hex!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+
+    const ABI: &str = r#"{"contractName": "WETH9", "abi": [
+        {"type": "function", "name": "transfer", "stateMutability": "nonpayable",
+         "inputs": [{"name": "dst", "type": "address"}, {"name": "wad", "type": "uint256"}],
+         "outputs": [{"name": "", "type": "bool"}]},
+        {"type": "function", "name": "adjust", "stateMutability": "nonpayable",
+         "inputs": [{"name": "delta", "type": "int256"}, {"name": "ids", "type": "uint8[]"}], "outputs": []},
+        {"type": "event", "name": "Transfer", "anonymous": false,
+         "inputs": [{"name": "src", "type": "address", "indexed": true},
+                    {"name": "dst", "type": "address", "indexed": true},
+                    {"name": "wad", "type": "uint256", "indexed": false}]}
+    ]}"#;
+
+    fn registry() -> AbiRegistry {
+        let mut registry = AbiRegistry::default();
+        registry.insert(H160::from(WETH), parse_contract(ABI).unwrap());
+        registry
+    }
+
+    #[test]
+    pub fn it_decodes_calls() {
+        let registry = registry();
+        let input = hex!("a9059cbb000000000000000000000000000000000000000000000000000000000000dead0000000000000000000000000000000000000000000000000de0b6b3a7640000");
+        let call = registry.decode_call(&H160::from(WETH), &input).unwrap();
+        assert_eq!(call.method, "transfer");
+        assert_eq!(call.signature, "transfer(address,uint256)");
+        assert_eq!(
+            call.args[0].value,
+            "0x000000000000000000000000000000000000dead"
+        );
+        assert_eq!(call.args[1].name, "wad");
+        assert_eq!(call.args[1].kind, "uint256");
+        assert_eq!(call.args[1].value, "1000000000000000000");
+
+        assert!(registry.decode_call(&H160::zero(), &input).is_none());
+        assert!(registry
+            .decode_call(&H160::from(WETH), &input[..3])
+            .is_none());
+    }
+
+    #[test]
+    pub fn it_decodes_signed_and_array_args() {
+        let registry = registry();
+        let contract = parse_contract(ABI).unwrap();
+        let function = contract.function("adjust").unwrap();
+        let input = function
+            .encode_input(&[
+                Token::Int(ethabi::Int::MAX - 4), // -5
+                Token::Array(vec![Token::Uint(1.into()), Token::Uint(2.into())]),
+            ])
+            .unwrap();
+        let call = registry.decode_call(&H160::from(WETH), &input).unwrap();
+        assert_eq!(call.args[0].value, "-5");
+        assert_eq!(call.args[1].value, serde_json::json!(["1", "2"]));
+    }
+
+    #[test]
+    pub fn it_decodes_logs() {
+        let registry = registry();
+        let log: ReceiptLog = serde_json::from_value(serde_json::json!({
+            "address": H160::from(WETH),
+            "data": "0x0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+            "logIndex": "0x0",
+            "topics": [
+                H256::from(hex!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef")),
+                H256::from_low_u64_be(0xbeef),
+                H256::from_low_u64_be(0xdead),
+            ]
+        }))
+        .unwrap();
+        let decoded = registry.decode_log(&log).unwrap();
+        assert_eq!(decoded.event, "Transfer");
+        assert_eq!(decoded.signature, "Transfer(address,address,uint256)");
+        assert_eq!(
+            decoded.args[0].value,
+            "0x000000000000000000000000000000000000beef"
+        );
+        assert_eq!(decoded.args[2].value, "1000000000000000000");
+    }
+}
@@ -1,3 +1,7 @@
+use crate::cache::{parse_ttl, CacheConfig, Probe};
+use crate::checker::{Checker, DEFAULT_CONCURRENCY};
+use crate::network::{Network, Retry, Settings, Thresholds};
+use std::time::Duration;
 use structopt::StructOpt;
 use tracing_subscriber::prelude::*;
 
@@ -7,16 +11,94 @@ use tracing_subscriber::prelude::*;
     about = "Enormous Cloud chainstate API server"
 )]
 pub struct Args {
-    /// Optional - plain text file, containing the list of RPC addresses to be checked.
+    /// Optional - file containing the list of RPC addresses to be checked:
+    /// plain text, or structured `.toml`/`.json` with per-endpoint settings.
     /// Tag may be appled to restrict the list
     #[structopt(short, long, default_value = "", env = "NETWORKS_FILE")]
     pub networks_file: String,
-    /// Filter chains by tag
+    /// Filter chains by tag expression, i.e. "(rsk or avalanche) and not testnet".
+    /// Comma-separated list with "-" for exclusion is accepted as well
     #[structopt(short, long, default_value = "")]
     pub tag: String,
-    /// Check single network address (internally used tags: nosync, nogaps)
+    /// Check single network address (internally used tags: nosync, nopeers, nogaps, gaps)
     #[structopt(long, default_value = "")]
     pub network: String,
+    /// Chain id that the single network address is expected to serve
+    #[structopt(long)]
+    pub chain_id: Option<u64>,
+    /// Age of the head block in seconds to report a warning,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub head_age_warn: Option<u64>,
+    /// Age of the head block in seconds to report a failure,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub head_age_fail: Option<u64>,
+    /// Minimum number of peers, below which a warning is reported,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub min_peers: Option<u64>,
+    /// Seconds between `newHeads` notifications of WebSocket endpoint to report a warning,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub new_heads_warn: Option<u64>,
+    /// Seconds between `newHeads` notifications of WebSocket endpoint to report a failure,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub new_heads_fail: Option<u64>,
+    /// Average latency of RPC calls in milliseconds to report a warning,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub latency_warn: Option<u64>,
+    /// Read timeout of RPC calls in seconds, unless the networks file sets it (default: 25)
+    #[structopt(long)]
+    pub timeout: Option<u64>,
+    /// Connect timeout of WebSocket RPC calls in seconds, unless the networks file sets it (default: 10)
+    #[structopt(long)]
+    pub connect_timeout: Option<u64>,
+    /// Time limit of RPC call including its retries in seconds,
+    /// unless the networks file sets it
+    #[structopt(long)]
+    pub deadline: Option<u64>,
+    /// Number of retries of failed RPC calls, unless the networks file sets it (default: 2)
+    #[structopt(long)]
+    pub retries: Option<u32>,
+    /// Number of networks checked at the same time (default: 64)
+    #[structopt(long)]
+    pub concurrency: Option<usize>,
+    /// Time limit of checking all networks in seconds,
+    /// networks that are not checked by then are reported as failed
+    #[structopt(long)]
+    pub run_deadline: Option<u64>,
+    /// Time to live of the cached probe result as `probe=seconds`, i.e. `chain_id=600`,
+    /// zero disables caching of the probe
+    #[structopt(long, parse(try_from_str = parse_ttl))]
+    pub cache_ttl: Vec<(Probe, u64)>,
+    /// Limit of cached probe results
+    #[structopt(long, default_value = "10000")]
+    pub cache_max_entries: usize,
+    /// Don't cache probe results, unless the networks file enables it for the endpoint
+    #[structopt(long)]
+    pub no_cache: bool,
+    /// Optional - TOML file with signatures of the events to classify transactions,
+    /// in addition to the standard ones
+    #[structopt(long, default_value = "")]
+    pub classes: String,
+    /// Optional - directory with contract ABI JSON files named by the contract address,
+    /// to decode calls and events of the transactions
+    #[structopt(long, default_value = "")]
+    pub abi_dir: String,
+    /// Optional - text file with method signatures, one per line, to name the methods
+    /// called by transactions, in addition to the bundled ones
+    #[structopt(long, default_value = "")]
+    pub signatures: String,
+    /// Request blocks only with transaction hashes, for slow endpoints. Transactions don't show
+    /// their value, method signature and call then
+    #[structopt(long)]
+    pub no_full_transactions: bool,
+    /// Print status of the checked networks as JSON lines instead of the log
+    #[structopt(long)]
+    pub json: bool,
     /// Return working endpoint (tag may be applied to restrict the list)
     #[structopt(long)]
     pub endpoints: bool,
@@ -28,6 +110,56 @@ pub struct Args {
     pub addr: String,
 }
 
+impl Args {
+    /// Engine checking the networks with the limits from command line
+    pub fn checker(&self) -> Checker {
+        Checker::new(
+            self.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            self.run_deadline.map(Duration::from_secs),
+        )
+    }
+
+    /// Cache of probe results, which is disabled for the networks that opt out of it
+    pub fn cache_config(&self, networks: &[Network]) -> CacheConfig {
+        CacheConfig {
+            ttl: self
+                .cache_ttl
+                .iter()
+                .map(|(probe, secs)| (*probe, Duration::from_secs(*secs)))
+                .collect(),
+            max_entries: self.cache_max_entries,
+            disabled: networks
+                .iter()
+                .filter(|x| x.cache == Some(false))
+                .map(|x| x.endpoint.clone())
+                .collect(),
+        }
+    }
+
+    /// Settings from command line, applied to networks that don't set their own
+    pub fn settings(&self) -> Settings {
+        Settings {
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            deadline: self.deadline,
+            cache: if self.no_cache { Some(false) } else { None },
+            retry: Retry {
+                count: self.retries,
+                ..Retry::default()
+            },
+            thresholds: Thresholds {
+                head_age_warn: self.head_age_warn,
+                head_age_fail: self.head_age_fail,
+                min_peers: self.min_peers,
+                new_heads_warn: self.new_heads_warn,
+                new_heads_fail: self.new_heads_fail,
+                latency_warn: self.latency_warn,
+                ..Thresholds::default()
+            },
+        }
+    }
+}
+
 pub fn parse() -> anyhow::Result<Args> {
     let log_level: String = std::env::var("LOG_LEVEL").unwrap_or("info".to_owned());
 
@@ -0,0 +1,418 @@
+use serde::Serialize;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Limit of cached results, unless set otherwise
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// JSON-RPC probes which results are cached, each with its own time to live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    ChainId,
+    NetVersion,
+    ClientVersion,
+    Syncing,
+    BlockNumber,
+    BlockInfo,
+    PeerCount,
+    BlockGaps,
+    Block,
+    State,
+}
+
+impl Probe {
+    pub const ALL: [Probe; 10] = [
+        Probe::ChainId,
+        Probe::NetVersion,
+        Probe::ClientVersion,
+        Probe::Syncing,
+        Probe::BlockNumber,
+        Probe::BlockInfo,
+        Probe::PeerCount,
+        Probe::BlockGaps,
+        Probe::Block,
+        Probe::State,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Probe::ChainId => "chain_id",
+            Probe::NetVersion => "net_version",
+            Probe::ClientVersion => "client_version",
+            Probe::Syncing => "syncing",
+            Probe::BlockNumber => "block_number",
+            Probe::BlockInfo => "block_info",
+            Probe::PeerCount => "peer_count",
+            Probe::BlockGaps => "block_gaps",
+            Probe::Block => "block",
+            Probe::State => "state",
+        }
+    }
+
+    /// Time to live of the result when it is not configured
+    pub fn default_ttl(self) -> Duration {
+        let secs = match self {
+            Probe::ChainId | Probe::NetVersion | Probe::ClientVersion => 3000,
+            Probe::Syncing | Probe::PeerCount => 15,
+            Probe::BlockNumber | Probe::BlockInfo => 5,
+            Probe::BlockGaps => 60,
+            Probe::Block => 30,
+            Probe::State => 10,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+impl FromStr for Probe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Probe::ALL
+            .iter()
+            .find(|x| x.name() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown probe {}", s))
+    }
+}
+
+/// Parses time to live of the probe given as `probe=seconds`
+pub fn parse_ttl(s: &str) -> Result<(Probe, u64), String> {
+    let (probe, secs) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected probe=seconds, got {}", s))?;
+    let secs = secs
+        .trim()
+        .parse()
+        .map_err(|e| format!("{}: {}", secs, e))?;
+    Ok((probe.trim().parse()?, secs))
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Time to live of the probes that differ from the default ones, zero disables the probe cache
+    pub ttl: HashMap<Probe, Duration>,
+    /// Limit of cached results, the ones that expire first are evicted to fit it
+    pub max_entries: usize,
+    /// Endpoints which results are never cached
+    pub disabled: HashSet<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: HashMap::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            disabled: HashSet::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn ttl(&self, probe: Probe) -> Duration {
+        self.ttl
+            .get(&probe)
+            .copied()
+            .unwrap_or_else(|| probe.default_ttl())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Results removed before their expiration to fit the limit of entries
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    fn add(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.evictions += other.evictions;
+    }
+}
+
+/// Counters of the cache, in total and per probe
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheReport {
+    pub entries: usize,
+    pub total: CacheStats,
+    pub probes: BTreeMap<Probe, CacheStats>,
+}
+
+/// Probe, endpoint and the argument of the probe, i.e. block number
+type Key = (Probe, String, u64);
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires: Instant,
+}
+
+/// Results of JSON-RPC probes. Only successful results are cached,
+/// so failing endpoint is asked again on the next check
+pub struct ProbeCache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<Key, Entry>>,
+    stats: Mutex<BTreeMap<Probe, CacheStats>>,
+}
+
+impl std::fmt::Debug for ProbeCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProbeCache")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProbeCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Whether results of the probe are cached for the endpoint
+    fn is_enabled(&self, probe: Probe, endpoint: &str) -> bool {
+        !self.config.ttl(probe).is_zero()
+            && self.config.max_entries > 0
+            && !self.config.disabled.contains(endpoint)
+    }
+
+    /// Cached result of the probe, unless it is expired or the cache is disabled for it
+    pub fn get<T: Clone + 'static>(&self, probe: Probe, endpoint: &str, arg: u64) -> Option<T> {
+        if !self.is_enabled(probe, endpoint) {
+            return None;
+        }
+        let value = self.lookup::<T>(&(probe, endpoint.to_string(), arg));
+        match value {
+            Some(_) => self.count(probe, |x| x.hits += 1),
+            None => self.count(probe, |x| x.misses += 1),
+        }
+        value
+    }
+
+    /// Caches the result of the probe for its time to live, unless the cache is disabled for it
+    pub fn put<T: Send + Sync + 'static>(&self, probe: Probe, endpoint: &str, arg: u64, value: T) {
+        if self.is_enabled(probe, endpoint) {
+            let key = (probe, endpoint.to_string(), arg);
+            self.insert(key, Arc::new(value), self.config.ttl(probe));
+        }
+    }
+
+    /// Cached result of the probe, otherwise the result of `fetch`, which is cached if it succeeds
+    pub async fn get_or_fetch<T, E, F, Fut>(
+        &self,
+        probe: Probe,
+        endpoint: &str,
+        arg: u64,
+        fetch: F,
+    ) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(value) = self.get(probe, endpoint, arg) {
+            return Ok(value);
+        }
+        let value = fetch().await?;
+        self.put(probe, endpoint, arg, value.clone());
+        Ok(value)
+    }
+
+    fn lookup<T: Clone + 'static>(&self, key: &Key) -> Option<T> {
+        let mut entries = self.entries.lock().ok()?;
+        let entry = entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+        entry.value.downcast_ref::<T>().cloned()
+    }
+
+    fn insert(&self, key: Key, value: Arc<dyn Any + Send + Sync>, ttl: Duration) {
+        let mut entries = match self.entries.lock() {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let now = Instant::now();
+        if entries.len() >= self.config.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, x| x.expires > now);
+        }
+        while entries.len() >= self.config.max_entries && !entries.contains_key(&key) {
+            let first = entries
+                .iter()
+                .min_by_key(|(_, x)| x.expires)
+                .map(|(k, _)| k.clone());
+            match first {
+                Some(k) => {
+                    entries.remove(&k);
+                    self.count(k.0, |x| x.evictions += 1);
+                }
+                None => break,
+            }
+        }
+        let expires = now + ttl;
+        entries.insert(key, Entry { value, expires });
+    }
+
+    fn count(&self, probe: Probe, f: impl FnOnce(&mut CacheStats)) {
+        if let Ok(mut stats) = self.stats.lock() {
+            f(stats.entry(probe).or_default());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|x| x.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn report(&self) -> CacheReport {
+        let probes = self.stats.lock().map(|x| x.clone()).unwrap_or_default();
+        let mut total = CacheStats::default();
+        for stats in probes.values() {
+            total.add(stats);
+        }
+        CacheReport {
+            entries: self.len(),
+            total,
+            probes,
+        }
+    }
+}
+
+static CACHE: OnceLock<ProbeCache> = OnceLock::new();
+
+/// Sets up the cache shared by the whole process.
+/// Returns false if it is already in use, keeping its configuration
+pub fn configure(config: CacheConfig) -> bool {
+    CACHE.set(ProbeCache::new(config)).is_ok()
+}
+
+/// Cache shared by the whole process, with default configuration unless it was configured
+pub fn shared() -> &'static ProbeCache {
+    CACHE.get_or_init(|| ProbeCache::new(CacheConfig::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use std::future::ready;
+
+    fn fetch(calls: &mut u32, value: u64) -> Result<u64, String> {
+        *calls += 1;
+        Ok(value)
+    }
+
+    #[test]
+    pub fn it_parses_ttl() {
+        assert_eq!(parse_ttl("chain_id=600"), Ok((Probe::ChainId, 600)));
+        assert_eq!(parse_ttl("block_number = 0"), Ok((Probe::BlockNumber, 0)));
+        assert!(parse_ttl("chain_id").is_err());
+        assert!(parse_ttl("chainid=5").is_err());
+    }
+
+    #[test]
+    pub fn it_counts_hits_and_misses() {
+        let cache = ProbeCache::new(CacheConfig::default());
+        let mut calls = 0;
+        for _ in 0..3 {
+            let res = task::block_on(cache.get_or_fetch(Probe::ChainId, "http://a", 0, || {
+                ready(fetch(&mut calls, 1))
+            }));
+            assert_eq!(res, Ok(1));
+        }
+        let res = task::block_on(cache.get_or_fetch(Probe::ChainId, "http://b", 0, || {
+            ready(fetch(&mut calls, 2))
+        }));
+        assert_eq!(res, Ok(2));
+        assert_eq!(calls, 2);
+        let report = cache.report();
+        assert_eq!(report.entries, 2);
+        assert_eq!(report.total.hits, 2);
+        assert_eq!(report.total.misses, 2);
+        assert_eq!(report.probes[&Probe::ChainId].misses, 2);
+    }
+
+    #[test]
+    pub fn it_skips_errors() {
+        let cache = ProbeCache::new(CacheConfig::default());
+        let res: Result<u64, String> =
+            task::block_on(cache.get_or_fetch(Probe::Syncing, "http://a", 0, || {
+                ready(Err("down".to_owned()))
+            }));
+        assert!(res.is_err());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn it_evicts_to_fit_limit() {
+        let mut config = CacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        };
+        config.ttl.insert(Probe::Block, Duration::from_secs(1));
+        let cache = ProbeCache::new(config);
+        let mut calls = 0;
+        for n in 0..3 {
+            let _ = task::block_on(
+                cache.get_or_fetch(Probe::Block, "http://a", n, || ready(fetch(&mut calls, n))),
+            );
+        }
+        // the block that expires first is evicted, others stay
+        let _ = task::block_on(
+            cache.get_or_fetch(Probe::Block, "http://a", 2, || ready(fetch(&mut calls, 2))),
+        );
+        assert_eq!(calls, 3);
+        let report = cache.report();
+        assert_eq!(report.entries, 2);
+        assert_eq!(report.total.evictions, 1);
+    }
+
+    #[test]
+    pub fn it_bypasses_disabled() {
+        let mut config = CacheConfig::default();
+        config.disabled.insert("http://a".to_owned());
+        config.ttl.insert(Probe::PeerCount, Duration::from_secs(0));
+        let cache = ProbeCache::new(config);
+        let mut calls = 0;
+        for _ in 0..2 {
+            let _ = task::block_on(cache.get_or_fetch(Probe::ChainId, "http://a", 0, || {
+                ready(fetch(&mut calls, 1))
+            }));
+            let _ = task::block_on(cache.get_or_fetch(Probe::PeerCount, "http://b", 0, || {
+                ready(fetch(&mut calls, 1))
+            }));
+        }
+        assert_eq!(calls, 4);
+        assert!(cache.is_empty());
+        assert_eq!(cache.report().total, CacheStats::default());
+    }
+
+    #[test]
+    pub fn it_puts_values() {
+        let mut config = CacheConfig::default();
+        config.disabled.insert("http://a".to_owned());
+        let cache = ProbeCache::new(config);
+        cache.put(Probe::State, "http://a", 0, 1u8);
+        cache.put(Probe::State, "http://b", 0, 2u8);
+        assert_eq!(cache.get::<u8>(Probe::State, "http://a", 0), None);
+        assert_eq!(cache.get::<u8>(Probe::State, "http://b", 0), Some(2));
+        assert_eq!(cache.get::<u8>(Probe::State, "http://c", 0), None);
+        let report = cache.report();
+        assert_eq!(report.entries, 1);
+        assert_eq!(report.probes[&Probe::State].hits, 1);
+        assert_eq!(report.probes[&Probe::State].misses, 1);
+    }
+}
@@ -1,25 +1,50 @@
+use crate::abi::{self, DecodedCall, DecodedLog};
+use crate::cache::{self, Probe};
+use crate::classifier;
+use crate::client::{ClientInfo, ClientKind};
+use crate::network::Network;
+use crate::receipts;
+use crate::rpc::{decode, to_u64, MethodLatency, RpcClient, RpcError};
+use crate::selectors;
+use crate::ws;
 use crate::State;
 use bytes::Bytes;
-use cached::proc_macro::cached;
 use ethereum_types::{H160, H256, U256, U64};
-use hex_literal::hex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::str::FromStr;
-use std::time::Duration;
-use tide::{Request, Response, Result};
-use ureq::{Agent, AgentBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tide::{Body, Request, Response, Result, StatusCode};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct EvmTx {
     pub txid: H256,
+    pub from: H160,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<H160>,
+    /// Value transferred in wei
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<U256>,
+    /// Signature of the called method by its 4-byte selector, i.e. `transfer(address,uint256)`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
     pub used: u64,
     pub price: U256,
+    /// The first of `classes`, kept for the consumers of the single class
     #[serde(skip_serializing_if = "Option::is_none")]
     pub class: Option<String>,
+    /// Classes by the events of the transaction, i.e. `Transfer` or `Swap`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
     pub status: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub contract_address: Option<H160>,
+    /// Method called by the transaction, when ABI of the contract is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call: Option<DecodedCall>,
+    /// Events of the contracts with known ABI
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<DecodedLog>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -29,6 +54,18 @@ pub struct EvmBlock {
     pub miner: H160,
     pub used: u64,
     pub limit: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_fee: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_gas_used: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excess_blob_gas: Option<u64>,
+    /// Number of withdrawals in the block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub withdrawals: Option<usize>,
+    /// Total amount of withdrawals in Gwei
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub withdrawn_gwei: Option<u64>,
     pub tx: Vec<EvmTx>,
 }
 
@@ -46,18 +83,19 @@ pub enum EvmSync {
     Done(bool),
 }
 
-impl EvmSync {
-    pub fn to_string(&self) -> String {
+impl std::fmt::Display for EvmSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
-            Self::Done(_) => "false".to_owned(),
+            Self::Done(_) => write!(f, "false"),
             Self::Progress {
                 current_block,
                 highest_block,
                 ..
             } => {
-                format!(
+                write!(
+                    f,
                     "{}% {} out of {}",
-                    current_block * 100 / highest_block,
+                    percent(current_block, highest_block),
                     current_block,
                     highest_block
                 )
@@ -66,21 +104,35 @@ impl EvmSync {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Sync progress in percents, zero when the highest block is unknown
+fn percent(current: U64, highest: U64) -> u128 {
+    match highest.as_u64() {
+        0 => 0,
+        x => current.as_u64() as u128 * 100 / x as u128,
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct EvmState {
     pub blocks: Vec<EvmBlock>,
+    /// Blocks that failed to be fetched or decoded, the other blocks are returned anyway
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<BlockError>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct EvmNumericResult {
-    pub result: U256,
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockError {
+    pub block_num: u64,
+    pub error: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RpcResponseBlockInfo {
+    /// Since London (EIP-1559)
     pub base_fee_per_gas: Option<U256>,
-    pub difficulty: U256,
+    /// Zero after the merge, some clients omit it
+    pub difficulty: Option<U256>,
     pub gas_limit: U256,
     pub gas_used: U256,
     pub hash: H256,
@@ -88,18 +140,88 @@ pub struct RpcResponseBlockInfo {
     pub number: U256,
     pub size: U256,
     pub timestamp: U256,
-    pub total_difficulty: U256,
-    pub transactions: Vec<H256>,
+    /// Omitted by the clients after the merge
+    pub total_difficulty: Option<U256>,
+    pub transactions: Vec<BlockTransaction>,
+    /// Since Shanghai (EIP-4895)
+    pub withdrawals: Option<Vec<Withdrawal>>,
+    pub withdrawals_root: Option<H256>,
+    /// Since Cancun (EIP-4844)
+    pub blob_gas_used: Option<U64>,
+    pub excess_blob_gas: Option<U64>,
+    /// Since Cancun (EIP-4788)
+    pub parent_beacon_block_root: Option<H256>,
+}
+
+/// Bytes from `0x`-prefixed hex string
+fn hex_bytes<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Bytes, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    hex::decode(s.trim_start_matches("0x"))
+        .map(Bytes::from)
+        .map_err(serde::de::Error::custom)
+}
+
+impl RpcResponseBlockInfo {
+    pub fn tx_hashes(&self) -> Vec<H256> {
+        self.transactions.iter().map(|x| x.hash()).collect()
+    }
+}
+
+/// Transaction of the block, as it is returned with the full transactions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub hash: H256,
+    pub from: H160,
+    /// Not set for contract deployment
+    pub to: Option<H160>,
+    pub value: U256,
+    #[serde(deserialize_with = "hex_bytes")]
+    pub input: Bytes,
+}
+
+/// Transactions of the block are hashes, unless the block is requested with full transactions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum BlockTransaction {
+    Hash(H256),
+    Full(Box<RpcTransaction>),
+}
+
+impl BlockTransaction {
+    pub fn hash(&self) -> H256 {
+        match self {
+            Self::Hash(x) => *x,
+            Self::Full(x) => x.hash,
+        }
+    }
+}
+
+/// Withdrawal of the validator from the beacon chain
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Withdrawal {
+    pub index: U64,
+    pub validator_index: U64,
+    pub address: H160,
+    /// Amount in Gwei
+    pub amount: U64,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ReceiptLog {
+    /// Contract that emitted the log
+    pub address: H160,
+    #[serde(deserialize_with = "hex_bytes")]
     pub data: Bytes,
     pub log_index: U256,
     pub removed: Option<bool>,
     pub topics: Vec<H256>,
-    pub transaction_log_index: U256,
+    /// Index of the log within the transaction, returned by OpenEthereum only
+    pub transaction_log_index: Option<U256>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -120,268 +242,846 @@ pub struct RpcResponseBlockReceiptsInfo {
 }
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct RpcError {
-    pub code: i32,
-    pub message: String,
+pub struct BlockGaps {
+    #[serde(rename = "blockGap")]
+    pub block_gap: Option<Vec<U256>>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct RpcErrorResponse {
-    pub id: serde_json::Value,
-    pub error: RpcError,
+impl BlockGaps {
+    pub fn is_empty(&self) -> bool {
+        self.block_gap
+            .as_ref()
+            .map(|x| x.is_empty())
+            .unwrap_or(true)
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct BlockGaps {
-    #[serde(rename = "blockGap")]
-    pub block_gap: Vec<U256>,
+impl std::fmt::Display for BlockGaps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s: Vec<String> = match &self.block_gap {
+            Some(gap) => gap.iter().map(|x| format!("{}", x)).collect(),
+            None => vec![],
+        };
+        write!(f, "{}", s.join(".."))
+    }
 }
 
-impl BlockGaps {
-    pub fn to_string(&self) -> String {
-        let s: Vec<String> = self.block_gap.iter().map(|x| format!("{}", x)).collect();
-        s.join("..")
+/// Severity of the endpoint status or of the single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Ok,
+    Warn,
+    Fail,
+    /// Check doesn't apply to the endpoint, i.e. disabled by tag or not supported by the node
+    Skip,
+}
+
+impl Level {
+    /// How bad the level is, skipped checks don't affect the status
+    fn severity(self) -> u8 {
+        match self {
+            Self::Ok | Self::Skip => 0,
+            Self::Warn => 1,
+            Self::Fail => 2,
+        }
     }
 }
 
+/// Result of the single check of the endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub level: Level,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Status of the endpoint. All checks are done, unless the endpoint doesn't report its chain,
+/// the level is the worst of their levels and the message lists their problems
 #[derive(Debug, Clone, Serialize)]
-#[serde(tag = "level", content = "msg")]
-pub enum EvmStatus {
-    Ok(String),
-    Warn(String),
-    Fail(String),
+pub struct EvmStatus {
+    pub level: Level,
+    /// Summary of the status, as it is logged
+    pub message: String,
+    pub endpoint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub chain_id: Option<u64>,
+    /// Network id from `net_version`
+    pub network_id: Option<u64>,
+    pub head_block: Option<u64>,
+    /// Age of the head block in seconds, when the head age is checked
+    pub head_age: Option<u64>,
+    pub sync: Option<EvmSync>,
+    pub client: Option<ClientInfo>,
+    pub peers: Option<u64>,
+    /// Duration of the check in milliseconds
+    pub latency_ms: u64,
+    /// Latency of the RPC methods called by this check, answers from the probe cache
+    /// are not measured
+    pub rpc_latency: BTreeMap<String, MethodLatency>,
+    pub checks: Vec<Check>,
 }
 
 impl EvmStatus {
+    fn new(network: &Network) -> Self {
+        Self {
+            level: Level::Ok,
+            message: String::new(),
+            endpoint: network.endpoint.clone(),
+            name: network.name.clone(),
+            chain_id: None,
+            network_id: None,
+            head_block: None,
+            head_age: None,
+            sync: None,
+            client: None,
+            peers: None,
+            latency_ms: 0,
+            rpc_latency: BTreeMap::new(),
+            checks: vec![],
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.level == Level::Ok
+    }
+
+    fn pass(&mut self, name: &'static str) {
+        self.checks.push(Check {
+            name,
+            level: Level::Ok,
+            reason: None,
+        });
+    }
+
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.checks.push(Check {
+            name,
+            level: Level::Skip,
+            reason: Some(reason.to_owned()),
+        });
+    }
+
+    /// Records the problem found by the check, which makes the level of the status not better
+    fn problem(&mut self, name: &'static str, level: Level, reason: String) {
+        if level.severity() > self.level.severity() {
+            self.level = level;
+        }
+        self.checks.push(Check {
+            name,
+            level,
+            reason: Some(reason),
+        });
+    }
+
+    /// Status with the message: the prefix, followed by the reasons of the problems
+    fn done(mut self, prefix: &str) -> Self {
+        let problems = self
+            .checks
+            .iter()
+            .filter(|x| x.level.severity() > 0)
+            .filter_map(|x| x.reason.as_deref());
+        let parts: Vec<&str> = Some(prefix)
+            .filter(|x| !x.is_empty())
+            .into_iter()
+            .chain(problems)
+            .collect();
+        self.message = parts.join(", ");
+        self
+    }
+
     pub fn log(&self) {
-        match self {
-            Self::Ok(msg) => tracing::info!("{}", msg),
-            Self::Warn(msg) => tracing::warn!("{}", msg),
-            Self::Fail(msg) => tracing::error!("{}", msg),
+        match self.level {
+            Level::Ok | Level::Skip => tracing::info!("{}", self.message),
+            Level::Warn => tracing::warn!("{}", self.message),
+            Level::Fail => tracing::error!("{}", self.message),
         }
     }
 
     pub fn log_with_address(&self, addr: &str) {
-        match self {
-            Self::Ok(msg) => tracing::info!("{}: {}", addr, msg),
-            Self::Warn(msg) => tracing::warn!("{}: {}", addr, msg),
-            Self::Fail(msg) => tracing::error!("{}: {}", addr, msg),
+        match self.level {
+            Level::Ok | Level::Skip => tracing::info!("{}: {}", addr, self.message),
+            Level::Warn => tracing::warn!("{}: {}", addr, self.message),
+            Level::Fail => tracing::error!("{}: {}", addr, self.message),
         }
     }
-}
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct RpcResponse<T> {
-    pub id: serde_json::Value,
-    pub result: T,
-}
+    /// Status of the network that was not checked within the time limit of the run
+    pub fn deadline_exceeded(network: &Network, deadline: Duration) -> Self {
+        let reason = format!("check is not done in {}s", deadline.as_secs_f64());
+        let mut status = Self::new(network);
+        status.problem("deadline", Level::Fail, reason);
+        status.done("")
+    }
 
-pub fn rpc_request(rpc_addr: &str) -> ureq::Request {
-    let agent: Agent = AgentBuilder::new()
-        .timeout_read(Duration::from_secs(25))
-        .build();
-    agent.post(rpc_addr).set("Content-Type", "application/json")
+    /// Prints the status as a single line of JSON
+    pub fn print_json(&self) {
+        match serde_json::to_string(self) {
+            Ok(x) => println!("{}", x),
+            Err(e) => tracing::error!("{}: {}", self.endpoint, e),
+        }
+    }
 }
 
-#[cached(time = 3000)]
-pub fn get_evm_chain_id(rpc_addr: String) -> std::result::Result<u64, String> {
-    let payload = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"net_version\",\"id\":\"1\"}}");
-    let rq = rpc_request(&rpc_addr);
-    let response: String = match rq.send_string(&payload) {
-        Ok(x) => x.into_string().unwrap(),
-        Err(e) => return Err(format!("{}", e)),
-    };
-    if let Ok(err) = serde_json::from_str::<RpcErrorResponse>(&response) {
-        return Err(err.error.message.clone());
-    }
-    let out: RpcResponse<serde_json::Value> = match serde_json::from_str(&response) {
-        Ok(x) => x,
-        Err(x) => return Err(x.to_string()),
-    };
-    match out.result {
-        serde_json::Value::Number(n) => return Ok(n.as_u64().unwrap()),
-        serde_json::Value::String(s) => return Ok(s.parse().unwrap()),
-        _ => return Err("result convertion failure".to_owned()),
+impl std::fmt::Display for EvmStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
-#[cached(time = 15)]
-pub fn get_evm_syncing(rpc_addr: String) -> std::result::Result<EvmSync, String> {
-    let payload = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"eth_syncing\",\"id\":\"1\"}}");
-    let rq = rpc_request(&rpc_addr);
-    let response: String = match rq.send_string(&payload) {
-        Ok(x) => x.into_string().unwrap(),
-        Err(e) => return Err(format!("{}", e)),
+/// Reads chain id from `net_version` or `eth_chainId` result,
+/// which may come as a number, decimal or hex string
+fn parse_chain_id(value: &serde_json::Value) -> std::result::Result<u64, RpcError> {
+    let parsed = match value {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
     };
-    if let Ok(err) = serde_json::from_str::<RpcErrorResponse>(&response) {
-        return Err(err.error.message.clone());
+    parsed.ok_or_else(|| RpcError::Decode(format!("result convertion failure: {}", value)))
+}
+
+/// EIP-155 chain id, as reported by `eth_chainId`
+pub async fn get_evm_eth_chain_id(client: &RpcClient) -> std::result::Result<u64, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::ChainId, client.endpoint(), 0, || async {
+            parse_chain_id(&client.call("eth_chainId", ()).await?)
+        })
+        .await
+}
+
+/// Network id, as reported by `net_version`
+pub async fn get_evm_net_version(client: &RpcClient) -> std::result::Result<u64, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::NetVersion, client.endpoint(), 0, || async {
+            parse_chain_id(&client.call("net_version", ()).await?)
+        })
+        .await
+}
+
+/// Chain id from `eth_chainId`, falling back to `net_version`
+/// for the nodes that don't support it
+pub async fn get_evm_chain_id(client: &RpcClient) -> std::result::Result<u64, RpcError> {
+    match get_evm_eth_chain_id(client).await {
+        Ok(x) => Ok(x),
+        Err(_) => get_evm_net_version(client).await,
     }
-    let out: RpcResponse<EvmSync> = match serde_json::from_str(&response) {
-        Ok(x) => x,
-        Err(x) => return Err(format!("{}. RESPONSE: {}", x.to_string(), response)),
-    };
-    Ok(out.result)
 }
 
-#[cached(time = 5)]
-pub fn get_evm_block_number(rpc_addr: String) -> std::result::Result<u64, String> {
-    let payload = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"eth_blockNumber\",\"id\":\"1\"}}");
-    let rq = rpc_request(&rpc_addr);
-    let response: String = match rq.send_string(&payload) {
-        Ok(x) => x.into_string().unwrap(),
-        Err(e) => return Err(format!("{}", e)),
-    };
-    if let Ok(err) = serde_json::from_str::<RpcErrorResponse>(&response) {
-        return Err(err.error.message.clone());
+/// Node software, as reported by `web3_clientVersion`.
+/// Result is `None` when the node doesn't expose it
+pub async fn get_evm_client_version(
+    client: &RpcClient,
+) -> std::result::Result<Option<ClientInfo>, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::ClientVersion, client.endpoint(), 0, || async {
+            let version: Option<String> = client.call_optional("web3_clientVersion", ()).await?;
+            Ok(version.map(|x| ClientInfo::parse(&x)))
+        })
+        .await
+}
+
+/// Sync progress of the node.
+/// Result is `None` when the node doesn't support `eth_syncing`
+pub async fn get_evm_syncing(client: &RpcClient) -> std::result::Result<Option<EvmSync>, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::Syncing, client.endpoint(), 0, || async {
+            client.call_optional("eth_syncing", ()).await
+        })
+        .await
+}
+
+pub async fn get_evm_block_number(client: &RpcClient) -> std::result::Result<u64, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::BlockNumber, client.endpoint(), 0, || async {
+            let out: U64 = client.call("eth_blockNumber", ()).await?;
+            Ok(out.as_u64())
+        })
+        .await
+}
+
+pub async fn get_evm_block_info(
+    client: &RpcClient,
+    block_num: u64,
+) -> std::result::Result<RpcResponseBlockInfo, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::BlockInfo, client.endpoint(), block_num, || async {
+            client
+                .call("eth_getBlockByNumber", (U64::from(block_num), false))
+                .await
+        })
+        .await
+}
+
+/// Number of peers connected to the node.
+/// Result is `None` when the node doesn't support `net_peerCount`
+pub async fn get_evm_peer_count(client: &RpcClient) -> std::result::Result<Option<u64>, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::PeerCount, client.endpoint(), 0, || async {
+            let out: Option<U64> = client.call_optional("net_peerCount", ()).await?;
+            Ok(out.map(|x| x.as_u64()))
+        })
+        .await
+}
+
+/// Gaps in the block history of OpenEthereum and Nethermind nodes, which appear
+/// after warp or fast sync until ancient blocks are downloaded.
+/// Result is `None` when the node doesn't support `parity_chainStatus`
+pub async fn get_evm_block_gaps(
+    client: &RpcClient,
+) -> std::result::Result<Option<BlockGaps>, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::BlockGaps, client.endpoint(), 0, || async {
+            client.call_optional("parity_chainStatus", ()).await
+        })
+        .await
+}
+
+/// Seconds passed since the given block timestamp
+fn block_age(timestamp: U256) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    if timestamp > U256::from(now) {
+        return 0;
     }
-    let out: RpcResponse<U64> = match serde_json::from_str(&response) {
-        Ok(x) => x,
-        Err(x) => return Err(format!("{}. RESPONSE: {}", x.to_string(), response)),
-    };
-    Ok(out.result.as_u64())
+    now - timestamp.as_u64()
+}
+
+/// Number of `newHeads` notifications to observe on WebSocket endpoints
+const NEW_HEADS_COUNT: usize = 3;
+
+/// Number of RPC calls the average latency should be measured on to be reported
+const MIN_LATENCY_CALLS: usize = 3;
+
+/// Status of the network, calls to the endpoint end by the given instant
+pub async fn get_evm_status(network: &Network, until: Option<Instant>) -> EvmStatus {
+    let started = Instant::now();
+    // latency is measured on the calls of this check, answers from the cache are not counted
+    let client = RpcClient::shared(&network.endpoint, &network.rpc_config())
+        .measured()
+        .until(until);
+    let mut status = check_evm_status(network, &client).await;
+    status.latency_ms = started.elapsed().as_millis() as u64;
+    status.rpc_latency = client.latency();
+    status
 }
 
-pub fn get_evm_status(rpc_addr: String, tags: &HashSet<String>) -> EvmStatus {
-    let chain_id = match get_evm_chain_id(rpc_addr.clone()) {
-        Ok(x) => x,
-        Err(err) => return EvmStatus::Fail(err.to_owned()),
+async fn check_evm_status(network: &Network, rpc: &RpcClient) -> EvmStatus {
+    let mut status = EvmStatus::new(network);
+    let rpc_addr = network.endpoint.clone();
+    let eth_chain_id = get_evm_eth_chain_id(rpc).await;
+    let net_version = get_evm_net_version(rpc).await;
+    let chain_id = match (&eth_chain_id, &net_version) {
+        (Ok(x), _) => *x,
+        (Err(_), Ok(x)) => *x,
+        // the endpoint that doesn't tell its chain is not checked any further
+        (Err(err), Err(_)) => {
+            status.problem("chain_id", Level::Fail, err.to_string());
+            return status.done("");
+        }
     };
-    if !tags.contains("nosync") {
-        match get_evm_syncing(rpc_addr.clone()) {
-            Ok(x) => {
-                if let EvmSync::Progress { .. } = x {
-                    return EvmStatus::Warn(format!("chain {}, {}", chain_id, x.to_string()));
-                }
-            }
-            Err(err) => {
-                let msg = err.to_owned();
-                // Some RPC APIs (i.e. arbitrum) don't have this method - and we will allow that
-                if !msg.contains("method eth_syncing") {
-                    return EvmStatus::Fail(msg);
+    status.chain_id = Some(chain_id);
+    status.network_id = net_version.as_ref().ok().copied();
+    let client = match get_evm_client_version(rpc).await {
+        Ok(Some(x)) => {
+            status.pass("client");
+            Some(x)
+        }
+        Ok(None) => {
+            status.skip("client", "web3_clientVersion is not supported");
+            None
+        }
+        // providers may block the method, then the client is unknown
+        Err(err) => {
+            status.skip("client", &err.to_string());
+            None
+        }
+    };
+    status.client = client.clone();
+    let kind = client.as_ref().map(|x| x.kind).unwrap_or(ClientKind::Other);
+    // both values are shown only when they differ
+    let mut chain = match net_version {
+        Ok(net) if net != chain_id => format!("chain {}, net {}", chain_id, net),
+        _ => format!("chain {}", chain_id),
+    };
+    if let Some(client) = &client {
+        chain = format!("{}, {}", chain, client);
+    }
+    match network.chain_id {
+        Some(expected) if expected != chain_id => {
+            let reason = format!("expected chain {}", expected);
+            status.problem("chain_id", Level::Fail, reason);
+        }
+        _ => status.pass("chain_id"),
+    }
+    // some chains (i.e. ethereum classic) have network id that differs from chain id,
+    // that should be declared for the endpoint, otherwise it is a misconfiguration
+    let unexpected_net = match (&eth_chain_id, &net_version) {
+        (Ok(id), Ok(net)) => id != net && network.network_id.is_none(),
+        _ => false,
+    };
+    match (network.network_id, &net_version) {
+        (Some(expected), Ok(net)) if expected != *net => {
+            let reason = format!("expected net {}", expected);
+            status.problem("network_id", Level::Fail, reason);
+        }
+        _ if unexpected_net => {
+            let reason = "chain id differs from network id".to_owned();
+            status.problem("network_id", Level::Warn, reason);
+        }
+        _ => status.pass("network_id"),
+    }
+    if network.tags.contains("nosync") {
+        status.skip("sync", "nosync tag");
+    } else if !kind.has_syncing() {
+        status.skip("sync", &format!("not supported by {}", kind.name()));
+    } else {
+        match get_evm_syncing(rpc).await {
+            Ok(Some(x)) => {
+                status.sync = Some(x.clone());
+                match x {
+                    EvmSync::Progress { .. } => status.problem("sync", Level::Warn, x.to_string()),
+                    EvmSync::Done(_) => status.pass("sync"),
                 }
             }
+            Ok(None) => status.skip("sync", "eth_syncing is not supported"),
+            Err(err) => status.problem("sync", Level::Fail, err.to_string()),
         };
     }
-    let head_block = match get_evm_block_number(rpc_addr.clone()) {
-        Ok(x) => x,
-        Err(err) => return EvmStatus::Fail(err.to_owned()),
+    let head_block = match get_evm_block_number(rpc).await {
+        Ok(x) => Some(x),
+        Err(err) => {
+            status.problem("head_block", Level::Fail, err.to_string());
+            None
+        }
     };
-    if head_block == 0 {
-        return EvmStatus::Warn(format!("chain {}, zero head block", chain_id));
+    status.head_block = head_block;
+    if let Some(head_block) = head_block {
+        let min_head_block = network.thresholds.min_head_block.unwrap_or(1);
+        if head_block < min_head_block {
+            let reason = if head_block == 0 {
+                "zero head block".to_owned()
+            } else {
+                format!("head block {} is below {}", head_block, min_head_block)
+            };
+            status.problem("head_block", Level::Warn, reason);
+        } else {
+            status.pass("head_block");
+        }
     }
-    EvmStatus::Ok(format!("chain {}, block {}", chain_id, head_block))
-}
-
-#[cached(time = 30)]
-pub fn get_evm_block(rpc_addr: String, block_num: u64) -> Option<EvmBlock> {
-    let payload1 = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"eth_getBlockByNumber\",\"params\":[\"0x{:x?}\",false],\"id\":\"i{}\"}}", block_num, block_num);
-    // tracing::debug!("RQ {}", payload1);
-    let rq1 = rpc_request(&rpc_addr);
-    let response1: String = rq1.send_string(&payload1).unwrap().into_string().unwrap();
-    let r1: RpcResponse<RpcResponseBlockInfo> = match serde_json::from_str(&response1) {
-        Ok(x) => x,
-        Err(e) => {
-            tracing::info!("eth_getBlockByNumber response {}", response1);
-            tracing::error!("parse error {}", e);
-            return None;
+    let head_age_warn = network.thresholds.head_age_warn;
+    let head_age_fail = network.thresholds.head_age_fail;
+    match head_block {
+        _ if head_age_warn.is_none() && head_age_fail.is_none() => {
+            status.skip("head_age", "no thresholds")
         }
-    };
-    // println!("{:#?}", r1);
-
-    let payload2 = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"parity_getBlockReceipts\",\"params\":[\"0x{:x?}\"],\"id\":\"r{}\"}}", block_num, block_num);
-    // tracing::debug!("RQ {}", payload2);
-    let rq2 = rpc_request(&rpc_addr);
-    let response2: String = rq2.send_string(&payload2).unwrap().into_string().unwrap();
-    let r2: RpcResponse<Vec<RpcResponseBlockReceiptsInfo>> = match serde_json::from_str(&response2)
-    {
-        Ok(x) => x,
-        Err(e) => {
-            tracing::info!("parity_getBlockReceipts response {}", response2);
-            tracing::error!("parse error {}", e);
-            return None;
+        None => status.skip("head_age", "head block is unknown"),
+        Some(head_block) => match get_evm_block_info(rpc, head_block).await {
+            Ok(head) => {
+                let age = block_age(head.timestamp);
+                status.head_age = Some(age);
+                let reason = format!("block {} is {}s old", head_block, age);
+                if head_age_fail.map(|x| age > x).unwrap_or(false) {
+                    status.problem("head_age", Level::Fail, reason);
+                } else if head_age_warn.map(|x| age > x).unwrap_or(false) {
+                    status.problem("head_age", Level::Warn, reason);
+                } else {
+                    status.pass("head_age");
+                }
+            }
+            Err(err) => status.problem("head_age", Level::Fail, err.to_string()),
+        },
+    }
+    let new_heads_warn = network.thresholds.new_heads_warn;
+    let new_heads_fail = network.thresholds.new_heads_fail;
+    if !ws::is_ws(&rpc_addr) {
+        status.skip("new_heads", "not a WebSocket endpoint");
+    } else if new_heads_warn.is_none() && new_heads_fail.is_none() {
+        status.skip("new_heads", "no thresholds");
+    } else {
+        // listening a bit longer than the largest threshold, so exceeding it is noticed
+        let limit = new_heads_warn.max(new_heads_fail).unwrap_or(0);
+        let silence = Duration::from_secs(limit + 1);
+        // too short watch would pass the stalled endpoint
+        if rpc.time_left().map(|x| x < silence).unwrap_or(false) {
+            status.skip("new_heads", "not enough time left to watch newHeads");
+        } else {
+            match ws::watch_new_heads(rpc, NEW_HEADS_COUNT, silence).await {
+                Ok(heads) => {
+                    let interval = heads.max_interval();
+                    let reason = if heads.heads.is_empty() {
+                        format!("no newHeads for {:.1}s", interval.as_secs_f64())
+                    } else {
+                        format!("newHeads stalled for {:.1}s", interval.as_secs_f64())
+                    };
+                    let exceeds = |x: u64| interval > Duration::from_secs(x);
+                    if new_heads_fail.map(exceeds).unwrap_or(false) {
+                        status.problem("new_heads", Level::Fail, reason);
+                    } else if new_heads_warn.map(exceeds).unwrap_or(false) {
+                        status.problem("new_heads", Level::Warn, reason);
+                    } else {
+                        status.pass("new_heads");
+                    }
+                }
+                Err(err) => status.problem("new_heads", Level::Fail, err.to_string()),
+            }
         }
-    };
-    // println!("{:#?}", r2);
-    let mut tx = vec![]; // TODO: map tx
-    for receipt in r2.result {
-        let mut class = None;
-        if receipt.logs.len() > 1 && receipt.logs[0].data.len() > 32 {
-            let hex_str = hex::encode(&receipt.logs[0].data);
-            let u256_str: String = hex_str.chars().take(64).collect();
-            let topic = U256::from_str(&u256_str).unwrap();
-            if topic
-                == hex!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").into()
-            {
-                class = Some("Transfer".to_owned());
+    }
+    if network.tags.contains("nopeers") {
+        status.skip("peers", "nopeers tag");
+    } else if !kind.has_peers() {
+        status.skip("peers", &format!("not supported by {}", kind.name()));
+    } else {
+        match get_evm_peer_count(rpc).await {
+            Ok(Some(peers)) => {
+                status.peers = Some(peers);
+                let min_peers = network.thresholds.min_peers.unwrap_or(1);
+                if peers == 0 {
+                    status.problem("peers", Level::Fail, "no peers".to_owned());
+                } else if peers < min_peers {
+                    let reason = format!("{} peers is below {}", peers, min_peers);
+                    status.problem("peers", Level::Warn, reason);
+                } else {
+                    status.pass("peers");
+                }
             }
+            // hosted providers often don't expose the number of peers,
+            // or reject the method with HTTP status or their own error code
+            Ok(None) => status.skip("peers", "net_peerCount is not supported"),
+            Err(err) => status.skip("peers", &err.to_string()),
         }
-        if let Some(_) = receipt.contract_address {
-            class = Some("Publish".to_owned());
+    }
+    // block gaps are checked unless `nogaps` tag is set,
+    // `gaps` tag requires the node to support the check
+    if network.tags.contains("nogaps") {
+        status.skip("block_gaps", "nogaps tag");
+    } else {
+        // nodes of unknown software are probed, as they may support the method
+        let gaps = if kind.has_block_gaps() || kind == ClientKind::Other {
+            get_evm_block_gaps(rpc).await
+        } else {
+            Ok(None)
+        };
+        let required = network.tags.contains("gaps");
+        match gaps {
+            Ok(Some(gaps)) if !gaps.is_empty() => {
+                let reason = format!("block gap {}", gaps);
+                status.problem("block_gaps", Level::Warn, reason);
+            }
+            Ok(Some(_)) => status.pass("block_gaps"),
+            Ok(None) if required => {
+                let reason = "block gaps are not reported".to_owned();
+                status.problem("block_gaps", Level::Fail, reason);
+            }
+            Ok(None) => status.skip("block_gaps", "not reported by the node"),
+            // the check is optional, so the node fails only when it is required by `gaps` tag
+            Err(err) if required => status.problem("block_gaps", Level::Fail, err.to_string()),
+            Err(err) => status.skip("block_gaps", &err.to_string()),
+        }
+    }
+    match &client {
+        Some(client) => match network.policy.check(client, chain_id) {
+            Some(reason) => status.problem("policy", Level::Warn, reason),
+            None => status.pass("policy"),
+        },
+        None => status.skip("policy", "client is unknown"),
+    }
+    match network.thresholds.latency_warn {
+        Some(limit) => {
+            // a single slow call doesn't make the endpoint slow
+            let (average, calls) = rpc.average_latency();
+            if calls < MIN_LATENCY_CALLS {
+                status.skip("latency", "not enough calls");
+            } else if average > Duration::from_millis(limit) {
+                let reason = format!(
+                    "average latency {}ms is above {}ms",
+                    average.as_millis(),
+                    limit
+                );
+                status.problem("latency", Level::Warn, reason);
+            } else {
+                status.pass("latency");
+            }
         }
+        None => status.skip("latency", "no thresholds"),
+    }
+    // problems are reported along with the chain and the head block
+    let at = match head_block {
+        Some(head_block) => format!("{}, block {}", chain, head_block),
+        None => chain,
+    };
+    status.done(&at)
+}
+
+static FULL_TRANSACTIONS: OnceLock<bool> = OnceLock::new();
+
+/// Sets up whether blocks are requested with full transactions, which are needed
+/// to show their value and to decode their calls. Returns false if it is already in use
+pub fn configure_full_transactions(full: bool) -> bool {
+    FULL_TRANSACTIONS.set(full).is_ok()
+}
+
+/// Whether blocks are requested with full transactions, true unless it was configured
+fn full_transactions() -> bool {
+    *FULL_TRANSACTIONS.get_or_init(|| true)
+}
+
+/// Block with the receipts of its transactions
+pub async fn get_evm_block(
+    client: &RpcClient,
+    block_num: u64,
+) -> std::result::Result<EvmBlock, RpcError> {
+    cache::shared()
+        .get_or_fetch(Probe::Block, client.endpoint(), block_num, || async {
+            let block: RpcResponseBlockInfo = client
+                .call(
+                    "eth_getBlockByNumber",
+                    (U64::from(block_num), full_transactions()),
+                )
+                .await?;
+            let receipts = receipts::get_block_receipts(client, &block).await?;
+            evm_block(block, receipts)
+        })
+        .await
+}
+
+fn evm_block(
+    block: RpcResponseBlockInfo,
+    receipts: Vec<RpcResponseBlockReceiptsInfo>,
+) -> std::result::Result<EvmBlock, RpcError> {
+    let classifier = classifier::shared();
+    let abi = abi::shared();
+    let signatures = selectors::shared();
+    let transactions: HashMap<H256, &RpcTransaction> = block
+        .transactions
+        .iter()
+        .filter_map(|x| match x {
+            BlockTransaction::Full(x) => Some((x.hash, x.as_ref())),
+            BlockTransaction::Hash(_) => None,
+        })
+        .collect();
+    let mut tx = vec![];
+    for receipt in receipts {
+        let classes = classifier.classify(&receipt);
+        let transaction = transactions.get(&receipt.transaction_hash);
+        let call = transaction.and_then(|x| abi.decode_call(&x.to?, &x.input));
+        let signature = transaction
+            .filter(|x| x.to.is_some())
+            .and_then(|x| signatures.lookup(&x.input))
+            .map(|x| x.to_owned());
+        let events = receipt
+            .logs
+            .iter()
+            .filter_map(|x| abi.decode_log(x))
+            .collect();
         tx.push(EvmTx {
             txid: receipt.transaction_hash,
-            used: receipt.gas_used.as_u64(),
+            from: receipt.from,
+            to: receipt.to,
+            value: transaction.map(|x| x.value),
+            signature,
+            used: to_u64("gas used", receipt.gas_used)?,
             price: receipt.effective_gas_price,
-            class,
-            status: receipt.status.as_u64(),
+            class: classes.first().cloned(),
+            classes,
+            status: to_u64("status", receipt.status)?,
             contract_address: receipt.contract_address,
+            call,
+            events,
         })
     }
 
-    Some(EvmBlock {
-        block_num: r1.result.number.as_u64(),
-        block_hash: r1.result.hash,
-        miner: r1.result.miner,
-        limit: r1.result.gas_limit.as_u64(),
-        used: r1.result.gas_used.as_u64(),
+    let withdrawn_gwei = match &block.withdrawals {
+        Some(x) => Some(x.iter().try_fold(0u64, |sum, w| {
+            Ok::<_, RpcError>(sum.saturating_add(to_u64("withdrawal amount", w.amount)?))
+        })?),
+        None => None,
+    };
+    Ok(EvmBlock {
+        block_num: to_u64("block number", block.number)?,
+        block_hash: block.hash,
+        miner: block.miner,
+        limit: to_u64("gas limit", block.gas_limit)?,
+        used: to_u64("gas used", block.gas_used)?,
+        base_fee: block.base_fee_per_gas,
+        blob_gas_used: block
+            .blob_gas_used
+            .map(|x| to_u64("blob gas used", x))
+            .transpose()?,
+        excess_blob_gas: block
+            .excess_blob_gas
+            .map(|x| to_u64("excess blob gas", x))
+            .transpose()?,
+        withdrawals: block.withdrawals.as_ref().map(|x| x.len()),
+        withdrawn_gwei,
         tx,
     })
 }
 
-#[cached(time = 10)]
-pub fn get_evm_state(rpc_addr: String, num_blocks: usize) -> Option<EvmState> {
-    let rq = rpc_request(&rpc_addr);
+/// Fetches the blocks with their receipts in a single JSON-RPC batch.
+/// Receipts are included when the endpoint returns them per block, otherwise
+/// they are fetched after the blocks. Error is returned when the endpoint
+/// doesn't accept batches, errors of the single blocks are returned in their places
+async fn get_evm_blocks_batch(
+    client: &RpcClient,
+    block_nums: &[u64],
+) -> std::result::Result<Vec<std::result::Result<EvmBlock, RpcError>>, RpcError> {
+    let strategy = receipts::strategy(client.endpoint());
+    let full = full_transactions();
+    let mut calls = vec![];
+    for block_num in block_nums {
+        let n = U64::from(*block_num);
+        calls.push(("eth_getBlockByNumber", serde_json::json!([n, full])));
+        if strategy.is_block_level() {
+            calls.push((strategy.method(), serde_json::json!([n])));
+        }
+    }
+    let mut results = client.batch(&calls).await?.into_iter();
+    let mut blocks = vec![];
+    while let Some(block) = results.next() {
+        let receipts = match strategy.is_block_level() {
+            true => results.next(),
+            false => None,
+        };
+        let block = async {
+            let block: RpcResponseBlockInfo = decode("eth_getBlockByNumber", block)?;
+            let receipts = match receipts {
+                Some(x) => receipts::from_batch(client, &block, strategy, x).await?,
+                None => receipts::get_block_receipts(client, &block).await?,
+            };
+            evm_block(block, receipts)
+        };
+        blocks.push(block.await);
+    }
+    Ok(blocks)
+}
+
+/// The latest blocks of the endpoint, with the errors of the blocks that failed.
+/// Result is `None` when the node is not synced and reports zero head block
+pub async fn get_evm_state(
+    client: &RpcClient,
+    num_blocks: usize,
+) -> std::result::Result<Option<EvmState>, RpcError> {
+    let arg = num_blocks as u64;
+    cache::shared()
+        .get_or_fetch(Probe::State, client.endpoint(), arg, || async {
+            let head_block = get_evm_block_number(client).await?;
+            tracing::info!("eth_blockNumber={}", head_block);
+            if head_block == 0 {
+                return Ok(None);
+            }
+
+            // the latest blocks, starting from the head
+            let block_nums: Vec<u64> = (1..num_blocks as u64)
+                .filter_map(|i| (head_block + 1).checked_sub(i))
+                .collect();
+            let blocks = match get_evm_blocks_batch(client, &block_nums).await {
+                Ok(x) => x,
+                // other errors would repeat for every block, i.e. the timeout of the hung endpoint
+                Err(RpcError::BatchRejected(e)) => {
+                    tracing::warn!("batch is rejected, fetching blocks one by one: {}", e);
+                    let mut blocks = vec![];
+                    for n in &block_nums {
+                        blocks.push(get_evm_block(client, *n).await);
+                    }
+                    blocks
+                }
+                Err(e) => return Err(e),
+            };
+            let mut state = EvmState::default();
+            for (block_num, block) in block_nums.iter().zip(blocks) {
+                match block {
+                    Ok(x) => state.blocks.push(x),
+                    Err(e) => {
+                        tracing::warn!("block {} failed: {}", block_num, e);
+                        state.errors.push(BlockError {
+                            block_num: *block_num,
+                            error: e.to_string(),
+                        });
+                    }
+                }
+            }
+            Ok(Some(state))
+        })
+        .await
+}
 
-    let payload = "{\"jsonrpc\":\"2.0\",\"method\":\"eth_blockNumber\",\"id\":1}";
-    let response: String = rq.send_string(payload).unwrap().into_string().unwrap();
-    let numeric: EvmNumericResult = serde_json::from_str(&response).unwrap();
-    tracing::info!("eth_blockNumber={}", numeric.result.as_u64());
-    if numeric.result == U256::from(0) {
-        // node that is not in sync will return 0
-        return None;
+/// HTTP status of the API response when the endpoint fails
+fn error_status(err: &RpcError) -> StatusCode {
+    match err {
+        RpcError::Timeout(_) => StatusCode::GatewayTimeout,
+        _ => StatusCode::BadGateway,
     }
+}
 
-    // building batch to get the latest blocks
-    let mut blocks = vec![];
-    for i in 1..num_blocks {
-        let block_num = numeric.result.as_u64() - (i as u64) + 1u64;
-        if let Some(b) = get_evm_block(rpc_addr.clone(), block_num) {
-            blocks.push(b)
+fn error_response(status: StatusCode, message: String) -> Result {
+    let mut res = Response::new(status);
+    res.set_body(Body::from_json(&serde_json::json!({ "error": message }))?);
+    Ok(res)
+}
+
+pub async fn get(req: Request<State>) -> Result {
+    let network = &req.state().eth1;
+    let client = RpcClient::shared(&network.endpoint, &network.rpc_config());
+    match get_evm_state(&client, 5).await {
+        Ok(Some(out)) => {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(Body::from_json(&out)?);
+            Ok(res)
+        }
+        Ok(None) => error_response(
+            StatusCode::ServiceUnavailable,
+            "node is not synced".to_owned(),
+        ),
+        Err(e) => {
+            tracing::error!("chainstate error {}", e);
+            error_response(error_status(&e), e.to_string())
         }
     }
-    Some(EvmState { blocks }) //, syncing: EvmSync::Done})
 }
 
-pub async fn get(req: Request<State>) -> Result {
-    let mut res = Response::new(200);
-    let rpc = req.state().eth1.clone();
-    let out = get_evm_state(rpc, 5);
+/// Time limit of checking the networks on the API request, unless the run deadline is set
+const NETWORKS_DEADLINE: Duration = Duration::from_secs(30);
+
+/// Status of all networks the server is watching
+pub async fn get_networks(req: Request<State>) -> Result {
+    let state = req.state();
+    let networks = state.networks.as_ref().clone();
+    let mut checker = state.checker.clone();
+    checker.deadline = checker.deadline.or(Some(NETWORKS_DEADLINE));
+    let statuses = checker.run(networks, |_, _| {}).await;
+    let mut res = Response::new(StatusCode::Ok);
+    res.set_body(Body::from_json(&statuses)?);
+    Ok(res)
+}
 
-    res.set_content_type("application/json");
-    res.set_body(serde_json::to_string(&out).unwrap());
+/// Counters of the probe cache
+pub async fn get_cache(_req: Request<State>) -> Result {
+    let mut res = Response::new(StatusCode::Ok);
+    res.set_body(Body::from_json(&cache::shared().report())?);
     Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rpc::{RpcConfig, RpcResponse, Transport};
+    use async_std::task;
+    use async_trait::async_trait;
     use ethereum_types::U64;
     use std::matches;
+    use std::sync::Arc;
 
     #[test]
     pub fn it_parses_done() {
         let input = r#"{"jsonrpc":"2.0","id":1,"result":false}"#;
-        let output: RpcResponse<EvmSync> = serde_json::from_str(&input).unwrap();
+        let output: RpcResponse<EvmSync> = serde_json::from_str(input).unwrap();
         assert!(matches!(output.result, EvmSync::Done(false)));
     }
 
@@ -390,7 +1090,7 @@ mod tests {
         let input = r#"
     {"jsonrpc":"2.0","id":1,"result":{"currentBlock":"0xceb358","highestBlock":"0xcf219e","knownStates":"0x0","pulledStates":"0x0","startingBlock":"0xceb358"}}
     "#;
-        let output: RpcResponse<EvmSync> = serde_json::from_str(&input).unwrap();
+        let output: RpcResponse<EvmSync> = serde_json::from_str(input).unwrap();
         match output.result {
             EvmSync::Progress {
                 starting_block,
@@ -410,7 +1110,7 @@ mod tests {
         let input = r#"
     {"jsonrpc":"2.0","result":{"currentBlock":"0xd1c504","highestBlock":"0x121534f","startingBlock":"0x0","warpChunksAmount":null,"warpChunksProcessed":null},"id":1}
     "#;
-        let output: RpcResponse<EvmSync> = serde_json::from_str(&input).unwrap();
+        let output: RpcResponse<EvmSync> = serde_json::from_str(input).unwrap();
         match output.result {
             EvmSync::Progress {
                 starting_block,
@@ -425,9 +1125,379 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn it_parses_chain_id() {
+        assert_eq!(parse_chain_id(&serde_json::json!("0x64")), Ok(100));
+        assert_eq!(parse_chain_id(&serde_json::json!("100")), Ok(100));
+        assert_eq!(parse_chain_id(&serde_json::json!(61)), Ok(61));
+        assert!(parse_chain_id(&serde_json::json!("0xzz")).is_err());
+        assert!(parse_chain_id(&serde_json::json!(null)).is_err());
+    }
+
+    #[test]
+    pub fn it_parses_block_gaps() {
+        let input = r#"{"jsonrpc":"2.0","result":{"blockGap":["0x1","0xd1c504"]},"id":1}"#;
+        let output: RpcResponse<BlockGaps> = serde_json::from_str(input).unwrap();
+        assert!(!output.result.is_empty());
+        assert_eq!(output.result.to_string(), "1..13747460");
+
+        let input = r#"{"jsonrpc":"2.0","result":{"blockGap":null},"id":1}"#;
+        let output: RpcResponse<BlockGaps> = serde_json::from_str(input).unwrap();
+        assert!(output.result.is_empty());
+    }
+
+    #[test]
+    pub fn it_measures_block_age() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let age = block_age(U256::from(now - 120));
+        assert!((120..125).contains(&age));
+        assert_eq!(block_age(U256::from(now + 60)), 0);
+    }
+
+    #[test]
+    pub fn it_renders_sync_progress() {
+        let sync = |current: u64, highest: u64| EvmSync::Progress {
+            starting_block: U64::zero(),
+            current_block: U64::from(current),
+            highest_block: U64::from(highest),
+        };
+        assert_eq!(sync(50, 200).to_string(), "25% 50 out of 200");
+        assert_eq!(sync(50, 0).to_string(), "0% 50 out of 0");
+        assert_eq!(
+            sync(u64::MAX, 1).to_string(),
+            format!("{}% {} out of 1", u64::MAX as u128 * 100, u64::MAX)
+        );
+    }
+
+    #[test]
+    pub fn it_fails_on_out_of_range_numbers() {
+        let input = r#"{"gasLimit":"0x10000000000000000","gasUsed":"0x0",
+        "hash":"0x88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6",
+        "miner":"0x05a56e2d52c817161883f50c441c3228cfe54d9f","number":"0x1","size":"0x219",
+        "timestamp":"0x55ba4224","transactions":[]}"#;
+        let block: RpcResponseBlockInfo = serde_json::from_str(input).unwrap();
+        assert!(matches!(evm_block(block, vec![]), Err(RpcError::Decode(_))));
+    }
+
+    #[test]
+    pub fn it_parses_legacy_block() {
+        let input = r#"{"difficulty":"0x3ff800000","extraData":"0x","gasLimit":"0x1388","gasUsed":"0x0",
+        "hash":"0x88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6",
+        "miner":"0x05a56e2d52c817161883f50c441c3228cfe54d9f","number":"0x1","size":"0x219",
+        "timestamp":"0x55ba4224","totalDifficulty":"0x7ff800000","transactions":[],"uncles":[]}"#;
+        let block: RpcResponseBlockInfo = serde_json::from_str(input).unwrap();
+        assert_eq!(block.total_difficulty, Some(U256::from(0x7ff800000u64)));
+        let block = evm_block(block, vec![]).unwrap();
+        assert_eq!(block.block_num, 1);
+        assert!(block.base_fee.is_none());
+        let json = serde_json::to_value(&block).unwrap();
+        assert!(json.get("withdrawals").is_none());
+        assert!(json.get("blob_gas_used").is_none());
+    }
+
+    #[test]
+    pub fn it_parses_cancun_block() {
+        let input = r#"{"baseFeePerGas":"0x3b9aca00","blobGasUsed":"0x40000","excessBlobGas":"0x20000",
+        "gasLimit":"0x1c9c380","gasUsed":"0x5208",
+        "hash":"0xa917fcc721a5465a484e9be17cda0cc5493933dd3bc70c9adbee192cb419c9d7",
+        "miner":"0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5","number":"0x12a05f2",
+        "parentBeaconBlockRoot":"0x5d6e2ee4e1d7a7e4bf2aee46b6b0bb3e5ea8cf2d6b4c0bc5d4f1f3b4a6b9c2d1",
+        "size":"0x220","timestamp":"0x65f1b057","transactions":[],
+        "withdrawals":[{"index":"0x2a1c5b1","validatorIndex":"0x10a5a","address":"0xb9d7934878b5fb9610b3fe8a5e441e8fad7e293f","amount":"0x11a3c5b"},
+        {"index":"0x2a1c5b2","validatorIndex":"0x10a5b","address":"0xb9d7934878b5fb9610b3fe8a5e441e8fad7e293f","amount":"0x10"}],
+        "withdrawalsRoot":"0x7a4ecf19774d15cf9c15adf0dd8e8a250c128b26c9e2ab2a08d6c9c8ffbd104f"}"#;
+        let block: RpcResponseBlockInfo = serde_json::from_str(input).unwrap();
+        assert!(block.difficulty.is_none());
+        assert!(block.total_difficulty.is_none());
+        assert!(block.parent_beacon_block_root.is_some());
+        let block = evm_block(block, vec![]).unwrap();
+        assert_eq!(block.blob_gas_used, Some(0x40000));
+        assert_eq!(block.excess_blob_gas, Some(0x20000));
+        assert_eq!(block.withdrawals, Some(2));
+        assert_eq!(block.withdrawn_gwei, Some(0x11a3c5b + 0x10));
+        assert_eq!(block.base_fee, Some(U256::from(1_000_000_000u64)));
+    }
+
+    #[test]
+    pub fn it_omits_missing_class() {
+        let tx = EvmTx {
+            txid: H256::zero(),
+            from: H160::zero(),
+            to: None,
+            value: None,
+            signature: None,
+            used: 21000,
+            price: U256::zero(),
+            class: None,
+            classes: vec![],
+            status: 1,
+            contract_address: None,
+            call: None,
+            events: vec![],
+        };
+        let json = serde_json::to_value(&tx).unwrap();
+        assert!(json.get("class").is_none());
+        assert!(json.get("classes").is_none());
+    }
+
+    #[test]
+    pub fn it_renders_status_report() {
+        let network = Network::new("http://localhost:4444", Default::default());
+        let mut status = EvmStatus::new(&network);
+        status.chain_id = Some(31);
+        status.head_block = Some(2451166);
+        status.pass("chain_id");
+        status.skip("sync", "nosync tag");
+        status.problem("peers", Level::Warn, "3 peers is below 5".to_owned());
+        let status = status.done("chain 31, block 2451166");
+        assert!(!status.is_ok());
+        assert_eq!(
+            status.to_string(),
+            "chain 31, block 2451166, 3 peers is below 5"
+        );
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["level"], "warn");
+        assert_eq!(json["chain_id"], 31);
+        assert_eq!(
+            json["checks"][0],
+            serde_json::json!({"name": "chain_id", "level": "ok"})
+        );
+        assert_eq!(json["checks"][1]["reason"], "nosync tag");
+        assert_eq!(json["checks"][2]["reason"], "3 peers is below 5");
+        assert!(json.get("name").is_none());
+        assert_eq!(json["rpc_latency"], serde_json::json!({}));
+
+        let status = EvmStatus::new(&network).done("chain 31, block 1");
+        assert!(status.is_ok());
+        assert_eq!(status.to_string(), "chain 31, block 1");
+
+        // checks continue after the problem, the level is the worst of them
+        let mut status = EvmStatus::new(&network);
+        status.problem("sync", Level::Warn, "50% 1 out of 2".to_owned());
+        status.problem("peers", Level::Fail, "no peers".to_owned());
+        status.problem(
+            "policy",
+            Level::Warn,
+            "geth 1.10.0 is below 1.13.12".to_owned(),
+        );
+        status.pass("latency");
+        let status = status.done("chain 1, block 1");
+        assert_eq!(status.level, Level::Fail);
+        assert_eq!(status.checks.len(), 4);
+        assert_eq!(
+            status.to_string(),
+            "chain 1, block 1, 50% 1 out of 2, no peers, geth 1.10.0 is below 1.13.12"
+        );
+    }
+
+    #[test]
+    pub fn it_maps_errors_to_http_status() {
+        let e = RpcError::Timeout("timed out reading response".to_owned());
+        assert_eq!(error_status(&e), StatusCode::GatewayTimeout);
+        let e = RpcError::HttpStatus {
+            status: 503,
+            body: "Service Unavailable".to_owned(),
+        };
+        assert_eq!(error_status(&e), StatusCode::BadGateway);
+        assert_eq!(e.to_string(), "HTTP status 503: Service Unavailable");
+    }
+
+    /// Node answering the blocks of the given numbers, any other block is undecodable.
+    /// Batches are rejected unless they are supported, or time out, counting the calls
+    #[derive(Debug, Default)]
+    struct Blocks {
+        blocks: Vec<u64>,
+        batches: bool,
+        batch_timeout: bool,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Blocks {
+        fn answer(&self, request: &serde_json::Value) -> serde_json::Value {
+            let result = match request["method"].as_str().unwrap() {
+                "eth_blockNumber" => serde_json::json!("0x3"),
+                "parity_getBlockReceipts" => serde_json::json!([]),
+                _ => {
+                    let n = u64::from_str_radix(&request["params"][0].as_str().unwrap()[2..], 16);
+                    match n {
+                        Ok(n) if self.blocks.contains(&n) => serde_json::json!({
+                            "difficulty": "0x0", "gasLimit": "0x1388", "gasUsed": "0x0",
+                            "hash": format!("0x{:064x}", n),
+                            "miner": "0x0000000000000000000000000000000000000000",
+                            "number": format!("0x{:x}", n), "size": "0x219", "timestamp": "0x0",
+                            "transactions": []
+                        }),
+                        _ => serde_json::json!("0xbad"),
+                    }
+                }
+            };
+            serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+        }
+    }
+
+    #[async_trait]
+    impl Transport for Blocks {
+        async fn send(
+            &self,
+            payload: &str,
+            _timeout: Duration,
+        ) -> std::result::Result<String, RpcError> {
+            self.calls
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let request: serde_json::Value = serde_json::from_str(payload).unwrap();
+            let response = match request.as_array() {
+                Some(_) if self.batch_timeout => {
+                    return Err(RpcError::Timeout("timed out reading response".to_owned()))
+                }
+                Some(_) if !self.batches => {
+                    let error = serde_json::json!({"code": -32600, "message": "batch requests are not supported"});
+                    serde_json::json!({"jsonrpc": "2.0", "id": null, "error": error})
+                }
+                Some(batch) => batch.iter().map(|x| self.answer(x)).collect(),
+                None => self.answer(&request),
+            };
+            Ok(response.to_string())
+        }
+    }
+
+    #[test]
+    pub fn it_returns_blocks_that_decode() {
+        let transport = Arc::new(Blocks {
+            blocks: vec![1, 3],
+            batches: true,
+            ..Default::default()
+        });
+        let client = RpcClient::with_transport("blocks", &RpcConfig::default(), transport);
+        let state = task::block_on(get_evm_state(&client, 4)).unwrap().unwrap();
+        let blocks: Vec<u64> = state.blocks.iter().map(|x| x.block_num).collect();
+        assert_eq!(blocks, vec![3, 1]);
+        assert_eq!(state.errors.len(), 1);
+        assert_eq!(state.errors[0].block_num, 2);
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["errors"][0]["block_num"], 2);
+    }
+
+    #[test]
+    pub fn it_fetches_blocks_one_by_one_when_batches_are_rejected() {
+        let transport = Arc::new(Blocks {
+            blocks: vec![1, 2, 3],
+            ..Default::default()
+        });
+        let client = RpcClient::with_transport("no-batches", &RpcConfig::default(), transport);
+        let state = task::block_on(get_evm_state(&client, 4)).unwrap().unwrap();
+        let blocks: Vec<u64> = state.blocks.iter().map(|x| x.block_num).collect();
+        assert_eq!(blocks, vec![3, 2, 1]);
+        assert!(state.errors.is_empty());
+    }
+
+    #[test]
+    pub fn it_returns_timed_out_batch_of_blocks() {
+        let transport = Arc::new(Blocks {
+            blocks: vec![1, 2, 3],
+            batch_timeout: true,
+            ..Default::default()
+        });
+        let client =
+            RpcClient::with_transport("batch-timeout", &RpcConfig::default(), transport.clone());
+        let out = task::block_on(get_evm_state(&client, 4));
+        assert!(matches!(out, Err(RpcError::Timeout(_))));
+        // the head block and the batch, the blocks are not fetched one by one
+        assert_eq!(
+            transport.calls.load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    /// Node answering the given methods with the fixed results, other methods are not found
+    #[derive(Debug)]
+    struct Node(Vec<(&'static str, serde_json::Value)>);
+
+    #[async_trait]
+    impl Transport for Node {
+        async fn send(
+            &self,
+            payload: &str,
+            _timeout: Duration,
+        ) -> std::result::Result<String, RpcError> {
+            let request: serde_json::Value = serde_json::from_str(payload).unwrap();
+            let id = &request["id"];
+            let response = match self
+                .0
+                .iter()
+                .find(|(method, _)| request["method"] == *method)
+            {
+                Some((_, result)) => {
+                    serde_json::json!({"jsonrpc": "2.0", "id": id, "result": result})
+                }
+                None => {
+                    let error = serde_json::json!({"code": -32601, "message": "method not found"});
+                    serde_json::json!({"jsonrpc": "2.0", "id": id, "error": error})
+                }
+            };
+            Ok(response.to_string())
+        }
+    }
+
+    #[test]
+    pub fn it_fails_on_unexpected_chain_id() {
+        let endpoint = "http://chain-31";
+        let node = Node(vec![
+            ("eth_chainId", serde_json::json!("0x1f")),
+            ("net_version", serde_json::json!("31")),
+            ("eth_syncing", serde_json::json!(false)),
+            ("eth_blockNumber", serde_json::json!("0x10")),
+            ("net_peerCount", serde_json::json!("0x5")),
+        ]);
+        let client = RpcClient::with_transport(endpoint, &RpcConfig::default(), Arc::new(node));
+        let mut network = Network::new(endpoint, Default::default());
+        network.chain_id = Some(30);
+        let status = task::block_on(check_evm_status(&network, &client));
+        assert_eq!(status.level, Level::Fail);
+        assert_eq!(status.chain_id, Some(31));
+        let check = status.checks.iter().find(|x| x.name == "chain_id").unwrap();
+        assert_eq!(check.level, Level::Fail);
+        assert_eq!(check.reason.as_deref(), Some("expected chain 30"));
+        assert_eq!(status.to_string(), "chain 31, block 16, expected chain 30");
+
+        // the same node serves the expected chain
+        network.chain_id = Some(31);
+        let status = task::block_on(check_evm_status(&network, &client));
+        assert_eq!(status.level, Level::Ok);
+    }
+
+    #[test]
+    pub fn it_skips_latency_without_enough_calls() {
+        let endpoint = "http://latency";
+        let node = Node(vec![
+            ("eth_chainId", serde_json::json!("0x1f")),
+            ("net_version", serde_json::json!("31")),
+            ("eth_syncing", serde_json::json!(false)),
+            ("eth_blockNumber", serde_json::json!("0x10")),
+            ("net_peerCount", serde_json::json!("0x5")),
+        ]);
+        let client = RpcClient::with_transport(endpoint, &RpcConfig::default(), Arc::new(node));
+        let mut network = Network::new(endpoint, Default::default());
+        network.thresholds.latency_warn = Some(60_000);
+        let latency = |status: &EvmStatus| {
+            let check = status.checks.iter().find(|x| x.name == "latency").unwrap();
+            (check.level, check.reason.clone())
+        };
+        let status = task::block_on(check_evm_status(&network, &client.measured()));
+        assert_eq!(latency(&status), (Level::Ok, None));
+
+        // the probes are answered from the cache, nothing is measured
+        let status = task::block_on(check_evm_status(&network, &client.measured()));
+        let reason = Some("not enough calls".to_owned());
+        assert_eq!(latency(&status), (Level::Skip, reason));
+    }
+
     #[test]
     pub fn it_reads_chain_id() {
-        let chain_id = get_evm_chain_id("https://dai.poa.network/".to_owned()).unwrap();
+        let client = RpcClient::new("https://dai.poa.network/", &RpcConfig::default());
+        let chain_id = task::block_on(get_evm_chain_id(&client)).unwrap();
         assert_eq!(chain_id, 100);
     }
 }
@@ -0,0 +1,184 @@
+use crate::chainstate::{get_evm_status, EvmStatus};
+use crate::network::Network;
+use async_std::channel;
+use async_std::future;
+use async_std::task;
+use std::time::{Duration, Instant};
+
+/// Number of networks checked at the same time, unless set otherwise
+pub const DEFAULT_CONCURRENCY: usize = 64;
+
+/// Checks the list of networks on the async runtime,
+/// with no more than `concurrency` of them at the same time
+#[derive(Debug, Clone)]
+pub struct Checker {
+    pub concurrency: usize,
+    /// Time limit of the whole run, networks that are not checked by then are reported as failed.
+    /// Checks that are still running are stopped, the queued ones are not started
+    pub deadline: Option<Duration>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONCURRENCY, None)
+    }
+}
+
+impl Checker {
+    pub fn new(concurrency: usize, deadline: Option<Duration>) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            deadline,
+        }
+    }
+
+    /// Checks the networks, calling `on_status` as soon as every status is ready.
+    /// Statuses are returned in the order of networks
+    pub async fn run<F>(&self, networks: Vec<Network>, mut on_status: F) -> Vec<EvmStatus>
+    where
+        F: FnMut(&Network, &EvmStatus),
+    {
+        let started = Instant::now();
+        let until = self.deadline.map(|x| started + x);
+        let (jobs_tx, jobs_rx) = channel::unbounded();
+        for job in networks.iter().cloned().enumerate() {
+            let _ = jobs_tx.try_send(job);
+        }
+        drop(jobs_tx);
+
+        let (results_tx, results_rx) = channel::unbounded();
+        let workers: Vec<_> = (0..self.concurrency.min(networks.len()))
+            .map(|_| {
+                let jobs = jobs_rx.clone();
+                let results = results_tx.clone();
+                task::spawn(async move {
+                    while let Ok((i, network)) = jobs.recv().await {
+                        let status = get_evm_status(&network, until).await;
+                        if results.send((i, status)).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(results_tx);
+
+        let mut statuses: Vec<Option<EvmStatus>> = vec![None; networks.len()];
+        loop {
+            let next = match self.deadline {
+                Some(limit) => {
+                    let left = limit.saturating_sub(started.elapsed());
+                    match future::timeout(left, results_rx.recv()).await {
+                        Ok(x) => x,
+                        Err(_) => break,
+                    }
+                }
+                None => results_rx.recv().await,
+            };
+            // checks that ended with the deadline didn't make it in time
+            let expired = self
+                .deadline
+                .map(|x| started.elapsed() >= x)
+                .unwrap_or(false);
+            match next {
+                Ok(_) if expired => break,
+                Ok((i, status)) => {
+                    on_status(&networks[i], &status);
+                    statuses[i] = Some(status);
+                }
+                // all workers are done
+                Err(_) => break,
+            }
+        }
+        // no more work after the deadline: queued checks are dropped, running ones are stopped
+        while jobs_rx.try_recv().is_ok() {}
+        jobs_rx.close();
+        for worker in workers {
+            worker.cancel().await;
+        }
+
+        let deadline = self.deadline.unwrap_or_default();
+        statuses
+            .into_iter()
+            .zip(networks.iter())
+            .map(|(status, network)| match status {
+                Some(x) => x,
+                None => {
+                    let status = EvmStatus::deadline_exceeded(network, deadline);
+                    on_status(network, &status);
+                    status
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chainstate::Level;
+    use crate::network::{Retry, Settings};
+    use std::collections::HashSet;
+    use std::net::TcpListener;
+
+    #[test]
+    pub fn it_returns_statuses_in_order() {
+        let endpoints: Vec<String> = (0..5)
+            .map(|_| {
+                // port that was just released is refusing connections
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                format!("http://{}", listener.local_addr().unwrap())
+            })
+            .collect();
+        let settings = Settings {
+            retry: Retry {
+                count: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let networks: Vec<Network> = endpoints
+            .iter()
+            .map(|x| {
+                let mut network = Network::new(x, HashSet::new());
+                network.inherit(&settings);
+                network
+            })
+            .collect();
+        let mut reported = vec![];
+        let statuses = task::block_on(
+            Checker::new(2, None).run(networks, |n, _| reported.push(n.endpoint.clone())),
+        );
+        assert_eq!(reported.len(), 5);
+        let checked: Vec<String> = statuses.iter().map(|x| x.endpoint.clone()).collect();
+        assert_eq!(checked, endpoints);
+        assert!(statuses.iter().all(|x| x.level == Level::Fail));
+    }
+
+    #[test]
+    pub fn it_stops_at_deadline() {
+        // connection is accepted by the backlog, but never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let networks = vec![Network::new(&endpoint, HashSet::new())];
+        let started = Instant::now();
+        let checker = Checker::new(1, Some(Duration::from_millis(300)));
+        let statuses = task::block_on(checker.run(networks, |_, _| {}));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(statuses[0].level, Level::Fail);
+        assert_eq!(statuses[0].checks[0].name, "deadline");
+    }
+
+    #[test]
+    pub fn it_ends_running_checks_at_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let network = Network::new(&endpoint, HashSet::new());
+        let started = Instant::now();
+        let until = started + Duration::from_millis(300);
+        let status = task::block_on(get_evm_status(&network, Some(until)));
+        // calls are not given the whole read timeout
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(status.level, Level::Fail);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::chainstate::{ReceiptLog, RpcResponseBlockReceiptsInfo};
+use ethereum_types::H256;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use tiny_keccak::{Hasher, Keccak};
+
+/// Class of the transaction that deploys a contract
+pub const PUBLISH: &str = "Publish";
+
+/// Events that are recognized without configuration: class, signature and number of topics.
+/// Transfers and approvals of ERC-20 and ERC-721 share the signature, but ERC-721 token id is indexed
+const STANDARD_EVENTS: &[(&str, &str, Option<usize>)] = &[
+    ("Transfer", "Transfer(address,address,uint256)", Some(3)),
+    ("Approval", "Approval(address,address,uint256)", Some(3)),
+    (
+        "ERC721Transfer",
+        "Transfer(address,address,uint256)",
+        Some(4),
+    ),
+    (
+        "ERC721Approval",
+        "Approval(address,address,uint256)",
+        Some(4),
+    ),
+    (
+        "ApprovalForAll",
+        "ApprovalForAll(address,address,bool)",
+        None,
+    ),
+    (
+        "ERC1155Transfer",
+        "TransferSingle(address,address,address,uint256,uint256)",
+        None,
+    ),
+    (
+        "ERC1155Transfer",
+        "TransferBatch(address,address,address,uint256[],uint256[])",
+        None,
+    ),
+    ("Deposit", "Deposit(address,uint256)", Some(2)),
+    ("Withdrawal", "Withdrawal(address,uint256)", Some(2)),
+    // Uniswap V2 and its forks
+    (
+        "Swap",
+        "Swap(address,uint256,uint256,uint256,uint256,address)",
+        None,
+    ),
+    // Uniswap V3 and its forks
+    (
+        "Swap",
+        "Swap(address,address,int256,int256,uint160,uint128,int24)",
+        None,
+    ),
+    // Uniswap V4
+    (
+        "Swap",
+        "Swap(bytes32,address,int128,int128,uint160,uint128,int24,uint24)",
+        None,
+    ),
+    // Curve
+    (
+        "Swap",
+        "TokenExchange(address,int128,uint256,int128,uint256)",
+        None,
+    ),
+    // Balancer V2 vault
+    (
+        "Swap",
+        "Swap(bytes32,address,address,uint256,uint256)",
+        None,
+    ),
+];
+
+/// Topic of the event with the given signature, i.e. `Transfer(address,address,uint256)`
+pub fn event_topic(signature: &str) -> H256 {
+    let mut hasher = Keccak::v256();
+    hasher.update(signature.as_bytes());
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    H256::from(out)
+}
+
+/// Normalized signature of the event: name with parameter types, without spaces
+fn parse_signature(signature: &str) -> Result<String, String> {
+    let normalized: String = signature.chars().filter(|x| !x.is_whitespace()).collect();
+    let valid = match normalized.split_once('(') {
+        Some((name, params)) => {
+            !name.is_empty()
+                && name.chars().all(|x| x.is_alphanumeric() || x == '_')
+                && params.ends_with(')')
+                && !params[..params.len() - 1].contains(['(', ')'])
+        }
+        None => false,
+    };
+    match valid {
+        true => Ok(normalized),
+        false => Err(format!("invalid event signature {}", signature)),
+    }
+}
+
+/// Event assigning the class to the transaction that emits it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub class: String,
+    /// Number of topics of the event, including the signature. Any number if not set
+    pub topics: Option<usize>,
+}
+
+impl Rule {
+    fn matches(&self, log: &ReceiptLog) -> bool {
+        self.topics.map(|x| x == log.topics.len()).unwrap_or(true)
+    }
+}
+
+/// Event declared in the classes file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventClass {
+    pub class: String,
+    /// Signature of the event, i.e. `OrderFilled(bytes32,address,uint256)`
+    pub signature: String,
+    pub topics: Option<usize>,
+}
+
+/// Classes file, with the events in `[[event]]` sections
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClassesFile {
+    #[serde(default)]
+    event: Vec<EventClass>,
+}
+
+/// Registry of the events by their signature topic (`topics[0]`),
+/// assigning classes to the transactions by the events in their receipts
+#[derive(Debug, Clone, Default)]
+pub struct Classifier {
+    rules: HashMap<H256, Vec<Rule>>,
+}
+
+impl Classifier {
+    /// Classifier of the standard token, WETH and DEX events
+    pub fn standard() -> Self {
+        let mut classifier = Self::default();
+        for (class, signature, topics) in STANDARD_EVENTS {
+            classifier
+                .add(class, signature, *topics)
+                .expect("standard event signature");
+        }
+        classifier
+    }
+
+    /// Registers the event signature for the class
+    pub fn add(
+        &mut self,
+        class: &str,
+        signature: &str,
+        topics: Option<usize>,
+    ) -> Result<(), String> {
+        let signature = parse_signature(signature)?;
+        let rules = self.rules.entry(event_topic(&signature)).or_default();
+        let rule = Rule {
+            class: class.to_string(),
+            topics,
+        };
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+        Ok(())
+    }
+
+    /// Standard classifier extended with the events from TOML file
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(Path::new(path))
+            .map_err(|e| anyhow::anyhow!("failed to open classes file {}: {}", path, e))?;
+        let file: ClassesFile = toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("failed to parse classes file {}: {}", path, e))?;
+        let mut classifier = Self::standard();
+        for event in file.event {
+            classifier
+                .add(&event.class, &event.signature, event.topics)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+        }
+        Ok(classifier)
+    }
+
+    /// Classes of the transaction, in order of its logs, without repeats
+    pub fn classify(&self, receipt: &RpcResponseBlockReceiptsInfo) -> Vec<String> {
+        let mut classes: Vec<String> = vec![];
+        if receipt.contract_address.is_some() {
+            classes.push(PUBLISH.to_owned());
+        }
+        for log in &receipt.logs {
+            let rules = match log.topics.first().and_then(|x| self.rules.get(x)) {
+                Some(x) => x,
+                None => continue,
+            };
+            for rule in rules.iter().filter(|x| x.matches(log)) {
+                if !classes.contains(&rule.class) {
+                    classes.push(rule.class.clone());
+                }
+            }
+        }
+        classes
+    }
+}
+
+static CLASSIFIER: OnceLock<Classifier> = OnceLock::new();
+
+/// Sets up the classifier shared by the whole process.
+/// Returns false if it is already in use
+pub fn configure(classifier: Classifier) -> bool {
+    CLASSIFIER.set(classifier).is_ok()
+}
+
+/// Classifier shared by the whole process, the standard one unless it was configured
+pub fn shared() -> &'static Classifier {
+    CLASSIFIER.get_or_init(Classifier::standard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::H160;
+    use hex_literal::hex;
+
+    const TRANSFER: [u8; 32] =
+        hex!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef");
+
+    fn log(topics: Vec<H256>) -> ReceiptLog {
+        serde_json::from_value(serde_json::json!({
+            "address": H160::zero(), "data": "0x", "logIndex": "0x0", "topics": topics
+        }))
+        .unwrap()
+    }
+
+    fn receipt(logs: Vec<ReceiptLog>) -> RpcResponseBlockReceiptsInfo {
+        let mut receipt: RpcResponseBlockReceiptsInfo = serde_json::from_value(serde_json::json!({
+            "blockHash": H256::zero(), "blockNumber": "0x1", "contractAddress": null,
+            "cumulativeGasUsed": "0x0", "effectiveGasPrice": "0x0", "from": H160::zero(),
+            "gasUsed": "0x0", "logs": [], "status": "0x1", "to": null,
+            "transactionHash": H256::zero(), "transactionIndex": "0x0"
+        }))
+        .unwrap();
+        receipt.logs = logs;
+        receipt
+    }
+
+    #[test]
+    pub fn it_hashes_signatures() {
+        assert_eq!(
+            event_topic("Transfer(address,address,uint256)"),
+            H256::from(TRANSFER)
+        );
+        assert_eq!(
+            parse_signature("Transfer(address, address, uint256)"),
+            Ok("Transfer(address,address,uint256)".to_owned())
+        );
+        assert!(parse_signature("Transfer").is_err());
+        assert!(parse_signature("(address)").is_err());
+    }
+
+    #[test]
+    pub fn it_classifies_token_transfers() {
+        let classifier = Classifier::standard();
+        let transfer = H256::from(TRANSFER);
+        let erc20 = log(vec![transfer, H256::zero(), H256::zero()]);
+        let erc721 = log(vec![transfer, H256::zero(), H256::zero(), H256::zero()]);
+        let swap = log(vec![
+            event_topic("Swap(address,uint256,uint256,uint256,uint256,address)"),
+            H256::zero(),
+            H256::zero(),
+        ]);
+        let classes = classifier.classify(&receipt(vec![erc20.clone(), swap, erc20]));
+        assert_eq!(classes, vec!["Transfer", "Swap"]);
+        let classes = classifier.classify(&receipt(vec![erc721]));
+        assert_eq!(classes, vec!["ERC721Transfer"]);
+        let deposit = log(vec![event_topic("Deposit(address,uint256)"), H256::zero()]);
+        assert_eq!(
+            classifier.classify(&receipt(vec![deposit])),
+            vec!["Deposit"]
+        );
+        assert!(classifier.classify(&receipt(vec![log(vec![])])).is_empty());
+
+        let mut publish = receipt(vec![]);
+        publish.contract_address = Some(H160::zero());
+        assert_eq!(classifier.classify(&publish), vec![PUBLISH]);
+    }
+
+    #[test]
+    pub fn it_reads_user_signatures() {
+        let path = std::env::temp_dir().join(format!("chainstate-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[event]]
+            class = "OrderFilled"
+            signature = "OrderFilled(bytes32, address, address, uint256, uint256, uint256, uint256, uint256)"
+
+            [[event]]
+            class = "Mint"
+            signature = "Transfer(address,address,uint256)"
+            topics = 3
+            "#,
+        )
+        .unwrap();
+        let classifier = Classifier::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let classifier = classifier.unwrap();
+        let order = log(vec![event_topic(
+            "OrderFilled(bytes32,address,address,uint256,uint256,uint256,uint256,uint256)",
+        )]);
+        let erc20 = log(vec![H256::from(TRANSFER), H256::zero(), H256::zero()]);
+        let classes = classifier.classify(&receipt(vec![order, erc20]));
+        assert_eq!(classes, vec!["OrderFilled", "Transfer", "Mint"]);
+    }
+
+    #[test]
+    pub fn it_reports_bad_classes_file() {
+        let err = Classifier::from_file("/nonexistent/classes.toml").unwrap_err();
+        assert!(err.to_string().starts_with("failed to open classes file"));
+
+        let path = std::env::temp_dir().join(format!("chainstate-bad-{}.toml", std::process::id()));
+        std::fs::write(&path, "[[event]]\nclass = \"Bad\"\nsignature = \"Bad\"\n").unwrap();
+        let err = Classifier::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Release version of the client, pre-release and build suffixes are ignored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Reads the leading `major.minor.patch` numbers, i.e. from `v1.10.8-stable-26675454`
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let src = src.trim().trim_start_matches('v');
+        let end = src
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(src.len());
+        if end == 0 {
+            return Err(format!("invalid version {}", src));
+        }
+        let mut numbers = src[..end].split('.').filter(|x| !x.is_empty());
+        let mut next = || -> Result<u64, String> {
+            match numbers.next() {
+                Some(x) => x.parse().map_err(|_| format!("invalid version {}", src)),
+                None => Ok(0),
+            }
+        };
+        let major = next()?;
+        let minor = next()?;
+        let patch = next()?;
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = String;
+
+    fn try_from(src: String) -> Result<Self, Self::Error> {
+        src.parse()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Family of the node software, detected from `web3_clientVersion`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientKind {
+    Geth,
+    Erigon,
+    Nethermind,
+    Besu,
+    OpenEthereum,
+    Reth,
+    Nitro,
+    Bor,
+    RskJ,
+    Other,
+}
+
+impl ClientKind {
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "geth" => Self::Geth,
+            "erigon" | "turbo-geth" => Self::Erigon,
+            "nethermind" => Self::Nethermind,
+            "besu" => Self::Besu,
+            "openethereum" | "parity-ethereum" | "parity" => Self::OpenEthereum,
+            "reth" => Self::Reth,
+            "nitro" => Self::Nitro,
+            "bor" => Self::Bor,
+            "rskj" => Self::RskJ,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Geth => "geth",
+            Self::Erigon => "erigon",
+            Self::Nethermind => "nethermind",
+            Self::Besu => "besu",
+            Self::OpenEthereum => "openethereum",
+            Self::Reth => "reth",
+            Self::Nitro => "nitro",
+            Self::Bor => "bor",
+            Self::RskJ => "rskj",
+            Self::Other => "other",
+        }
+    }
+
+    /// Whether `eth_syncing` reflects the state of the node.
+    /// Arbitrum nodes follow the sequencer and don't implement it
+    pub fn has_syncing(&self) -> bool {
+        !matches!(self, Self::Nitro)
+    }
+
+    /// Whether the node has its own p2p network, so `net_peerCount` makes sense
+    pub fn has_peers(&self) -> bool {
+        !matches!(self, Self::Nitro)
+    }
+
+    /// Whether the node reports gaps in block history via `parity_chainStatus`
+    pub fn has_block_gaps(&self) -> bool {
+        matches!(self, Self::OpenEthereum | Self::Nethermind)
+    }
+}
+
+/// Node software, as reported by `web3_clientVersion`,
+/// i.e. `Geth/v1.10.8-stable-26675454/linux-amd64/go1.16.4`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientInfo {
+    pub kind: ClientKind,
+    /// Release of the client, i.e. `v1.10.8-stable-26675454`
+    pub release: Option<String>,
+    /// Full client version string
+    pub raw: String,
+}
+
+impl ClientInfo {
+    pub fn parse(raw: &str) -> Self {
+        let mut parts = raw.split('/').map(|x| x.trim()).filter(|x| !x.is_empty());
+        let name = parts.next().unwrap_or("");
+        // release is the first segment starting with a digit, optionally prefixed with "v"
+        let release = parts
+            .find(|x| {
+                x.trim_start_matches('v')
+                    .chars()
+                    .next()
+                    .map(|c| c.is_ascii_digit())
+                    .unwrap_or(false)
+            })
+            .map(|x| x.to_string());
+        Self {
+            kind: ClientKind::from_name(name),
+            release,
+            raw: raw.to_string(),
+        }
+    }
+
+    /// Semantic version of the release, if it could be parsed
+    pub fn version(&self) -> Option<Version> {
+        self.release.as_ref().and_then(|x| x.parse().ok())
+    }
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.kind {
+            ClientKind::Other => self.raw.split('/').next().unwrap_or(""),
+            _ => self.kind.name(),
+        };
+        match &self.release {
+            Some(release) => write!(f, "{} {}", name, release),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_parses_client_versions() {
+        let c = ClientInfo::parse("Geth/v1.10.8-stable-26675454/linux-amd64/go1.16.4");
+        assert_eq!(c.kind, ClientKind::Geth);
+        assert_eq!(c.release.as_deref(), Some("v1.10.8-stable-26675454"));
+        assert_eq!(c.to_string(), "geth v1.10.8-stable-26675454");
+        assert_eq!(c.version().unwrap().to_string(), "1.10.8");
+
+        let c = ClientInfo::parse(
+            "OpenEthereum//v3.3.0-rc.15-stable-3cb7f5f5a-20211108/x86_64-linux-gnu/rustc1.52.1",
+        );
+        assert_eq!(c.kind, ClientKind::OpenEthereum);
+        assert_eq!(
+            c.release.as_deref(),
+            Some("v3.3.0-rc.15-stable-3cb7f5f5a-20211108")
+        );
+        assert_eq!(c.version().unwrap().to_string(), "3.3.0");
+
+        let c = ClientInfo::parse("erigon/2.48.1/linux-amd64/go1.20.5");
+        assert_eq!(c.kind, ClientKind::Erigon);
+        assert_eq!(c.release.as_deref(), Some("2.48.1"));
+
+        let c = ClientInfo::parse("Nethermind/v1.20.1+9c4bf2d5/linux-x64/dotnet7.0.8");
+        assert_eq!(c.kind, ClientKind::Nethermind);
+        assert!(c.kind.has_block_gaps());
+
+        let c = ClientInfo::parse("nitro/v2.0.14-2baa834/linux-amd64/go1.19.10");
+        assert_eq!(c.kind, ClientKind::Nitro);
+        assert!(!c.kind.has_syncing());
+
+        let c = ClientInfo::parse("RskJ/4.0.0/Linux/Java1.8/HOP-c6a8c3b");
+        assert_eq!(c.kind, ClientKind::RskJ);
+
+        let c = ClientInfo::parse("v0.8.13");
+        assert_eq!(c.kind, ClientKind::Other);
+        assert_eq!(c.release, None);
+        assert_eq!(c.version(), None);
+        assert_eq!(c.to_string(), "v0.8.13");
+    }
+
+    #[test]
+    pub fn it_compares_versions() {
+        let v = |x: &str| x.parse::<Version>().unwrap();
+        assert!(v("v1.10.8-stable") < v("1.10.17"));
+        assert!(v("1.13") < v("1.13.1"));
+        assert!(v("2.0.0") > v("1.99.99"));
+        assert_eq!(v("v23.4.1+abc"), v("23.4.1"));
+        assert!("stable".parse::<Version>().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+#[cfg(unix)]
+use crate::rpc::{io_error, is_notification, timed_out, RpcError, Transport};
+#[cfg(unix)]
+use async_std::future;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use async_std::sync::Mutex;
+#[cfg(unix)]
+use async_trait::async_trait;
+#[cfg(unix)]
+use futures::{AsyncReadExt, AsyncWriteExt};
+#[cfg(unix)]
+use std::time::Duration;
+
+/// Whether the endpoint is a filesystem socket: `ipc:///data/geth.ipc` or a path to `.ipc` file
+pub fn is_ipc(endpoint: &str) -> bool {
+    endpoint.starts_with("ipc://") || (!endpoint.contains("://") && endpoint.ends_with(".ipc"))
+}
+
+/// Filesystem path of the socket
+pub fn socket_path(endpoint: &str) -> &str {
+    endpoint.strip_prefix("ipc://").unwrap_or(endpoint)
+}
+
+/// Open socket with the bytes received after the last message
+#[cfg(unix)]
+#[derive(Debug)]
+struct Connection {
+    stream: UnixStream,
+    received: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl Connection {
+    /// Next message of the socket. Messages are not delimited,
+    /// so the message ends where its JSON value ends
+    async fn read(&mut self) -> Result<String, RpcError> {
+        loop {
+            let mut values = serde_json::Deserializer::from_slice(&self.received)
+                .into_iter::<serde_json::Value>();
+            match values.next() {
+                Some(Ok(x)) => {
+                    let end = values.byte_offset();
+                    self.received.drain(..end);
+                    return Ok(x.to_string());
+                }
+                Some(Err(e)) if !e.is_eof() => return Err(RpcError::Decode(e.to_string())),
+                // the value is not received completely yet
+                _ => {}
+            }
+            let mut buf = [0u8; 4096];
+            let n = self.stream.read(&mut buf).await.map_err(io_error)?;
+            if n == 0 {
+                return Err(RpcError::Transport("connection closed".to_owned()));
+            }
+            self.received.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    async fn exchange(&mut self, payload: &str) -> Result<String, RpcError> {
+        self.stream
+            .write_all(payload.as_bytes())
+            .await
+            .map_err(io_error)?;
+        loop {
+            let body = self.read().await?;
+            if !is_notification(&body) {
+                return Ok(body);
+            }
+        }
+    }
+}
+
+/// JSON-RPC over Unix domain socket. The connection is opened on the first request,
+/// kept for the following ones and reopened after a failure. Available on Unix only
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct IpcTransport {
+    path: String,
+    connection: Mutex<Option<Connection>>,
+}
+
+#[cfg(unix)]
+impl IpcTransport {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            path: socket_path(endpoint).to_string(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection, RpcError> {
+        let stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(|e| RpcError::Transport(format!("{}: {}", self.path, e)))?;
+        Ok(Connection {
+            stream,
+            received: vec![],
+        })
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Transport for IpcTransport {
+    async fn send(&self, payload: &str, timeout: Duration) -> Result<String, RpcError> {
+        let mut guard = self.connection.lock().await;
+        let exchange = async {
+            if guard.is_none() {
+                *guard = Some(self.connect().await?);
+            }
+            let conn = guard.as_mut().expect("connection is open");
+            conn.exchange(payload).await
+        };
+        let result = future::timeout(timeout, exchange)
+            .await
+            .map_err(|_| timed_out(timeout))
+            .and_then(|x| x);
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_detects_ipc_endpoints() {
+        assert!(is_ipc("ipc:///data/geth.ipc"));
+        assert!(is_ipc("/data/geth.ipc"));
+        assert!(!is_ipc("/data/networks.txt"));
+        assert!(!is_ipc("http://localhost:8545"));
+        assert!(!is_ipc("https://node.example/geth.ipc"));
+        assert_eq!(socket_path("ipc:///data/geth.ipc"), "/data/geth.ipc");
+        assert_eq!(socket_path("/data/geth.ipc"), "/data/geth.ipc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn it_reads_responses_from_socket() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("chainstate-{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            for _ in 0..2 {
+                let _ = stream.read(&mut buf).unwrap();
+                // notification and response arrive together, without delimiters
+                stream
+                    .write_all(
+                        br#"{"jsonrpc":"2.0","method":"eth_subscription","params":{}}{"jsonrpc":"2.0","id":1,"result":"0x10"}"#,
+                    )
+                    .unwrap();
+            }
+        });
+        let endpoint = format!("ipc://{}", path.display());
+        let transport = IpcTransport::new(&endpoint);
+        for _ in 0..2 {
+            let body = async_std::task::block_on(
+                transport.send(r#"{"jsonrpc":"2.0","id":1}"#, Duration::from_secs(5)),
+            )
+            .unwrap();
+            assert_eq!(body, r#"{"id":1,"jsonrpc":"2.0","result":"0x10"}"#);
+        }
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
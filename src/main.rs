@@ -1,28 +1,32 @@
+pub mod abi;
 pub mod args;
+pub mod cache;
 pub mod chainstate;
+pub mod checker;
+pub mod classifier;
+pub mod client;
+pub mod ipc;
 pub mod network;
+pub mod policy;
+pub mod receipts;
+pub mod rpc;
+pub mod selectors;
+pub mod tags;
 pub mod telemetry;
+pub mod ws;
 
-use crate::chainstate::{get_evm_status, EvmStatus};
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
+use crate::checker::Checker;
+use crate::network::Network;
+use crate::tags::TagExpr;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct State {
-    pub eth1: String,
-}
-
-pub fn tags_from_args(tags_str: &str) -> HashSet<String> {
-    let mut tags: HashSet<String> = HashSet::new();
-    let parts: Vec<&str> = tags_str.trim().split(",").collect();
-    if parts.len() > 0 {
-        for part in parts {
-            if part.trim().len() > 0 {
-                tags.insert(part.trim().to_string());
-            }
-        }
-    }
-    tags
+    /// Network reported by `/api/chainstate`, with its settings
+    pub eth1: Network,
+    /// Networks reported by `/api/networks`
+    pub networks: Arc<Vec<Network>>,
+    pub checker: Checker,
 }
 
 #[async_std::main]
@@ -34,73 +38,95 @@ async fn main() -> tide::Result<()> {
         }
     };
 
-    if args.endpoints {
-        // show working endpoints in plain text format
-        let arc_tags = Arc::new(tags_from_args(&args.tag));
-        let mut threads = vec![];
-        let matches: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-        for network in network::from_file(&args.networks_file).unwrap() {
-            let tag = Arc::clone(&arc_tags);
-            let amatches = Arc::clone(&matches);
-            // for each network spawn a thread that logs its status
-            threads.push(std::thread::spawn(move || {
-                if network.has_all(&tag) {
-                    let addr = network.endpoint.clone();
-                    if let EvmStatus::Ok(_) = get_evm_status(addr.clone(), &network.tags) {
-                        let mut m = amatches.lock().unwrap();
-                        m.push(addr.clone());
-                    }
-                }
-            }));
-        }
-        // wait for result
-        for t in threads {
-            let _ = t.join();
-        }
-        // return found endpoints into stdout
-        let result = matches.lock().unwrap();
-        for l in result.iter() {
-            println!("{}", l);
+    let filter = match TagExpr::parse(&args.tag) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Tag filter error: {}", e);
+            std::process::exit(2);
         }
-        return Ok(());
-    }
+    };
 
-    if args.network.len() > 0 {
-        let network = args.network.clone();
-        let tags = tags_from_args(&args.tag);
-        get_evm_status(network.clone(), &tags).log();
-        return Ok(());
-    }
+    let settings = args.settings();
+    let checker = args.checker();
 
-    if args.networks_file.len() > 0 {
-        let arc_tag = Arc::new(tags_from_args(&args.tag));
-        let mut threads = vec![];
-        for network in network::from_file(&args.networks_file).unwrap() {
-            let tags = Arc::clone(&arc_tag);
-            // for each network spawn a thread that logs its status
-            threads.push(std::thread::spawn(move || {
-                if network.has_all(&tags) {
-                    let addr = network.endpoint.clone();
-                    get_evm_status(addr.clone(), &network.tags).log_with_address(&addr);
-                }
-            }));
-        }
-        // wait for result
-        for t in threads {
-            let _ = t.join();
+    // networks to be checked: either the single address, or the filtered list from the file
+    let networks: Vec<Network> = if !args.network.is_empty() {
+        // tags of the single network are the ones the filter requires
+        let mut network = Network::new(&args.network, filter.required_tags());
+        network.chain_id = args.chain_id;
+        network.inherit(&settings);
+        vec![network]
+    } else if !args.networks_file.is_empty() {
+        let mut networks = network::from_file(&args.networks_file)?;
+        networks.retain(|x| x.matches(&filter));
+        for network in &mut networks {
+            network.inherit(&settings);
         }
-        return Ok(());
+        networks
+    } else {
+        vec![]
+    };
+
+    cache::configure(args.cache_config(&networks));
+    if !args.classes.is_empty() {
+        classifier::configure(classifier::Classifier::from_file(&args.classes)?);
+    }
+    if !args.abi_dir.is_empty() {
+        abi::configure(abi::AbiRegistry::from_dir(&args.abi_dir)?);
     }
+    if !args.signatures.is_empty() {
+        selectors::configure(selectors::SignatureDb::from_file(&args.signatures)?);
+    }
+    chainstate::configure_full_transactions(!args.no_full_transactions);
 
     if args.server {
+        let eth1 = match networks.iter().find(|x| x.endpoint == args.network) {
+            Some(x) => x.clone(),
+            None => {
+                let mut network = Network::new(&args.network, Default::default());
+                network.inherit(&settings);
+                network
+            }
+        };
         let state = State {
-            eth1: args.network.clone(),
+            eth1,
+            networks: Arc::new(networks),
+            checker,
         };
         let mut app = tide::with_state(state);
         app.with(telemetry::TraceMiddleware::new());
         // app.with(ServeMiddleware {});
         app.at("/api/chainstate").get(chainstate::get);
+        app.at("/api/networks").get(chainstate::get_networks);
+        app.at("/api/cache").get(chainstate::get_cache);
         app.listen(args.addr.as_str()).await?;
+        return Ok(());
+    }
+
+    if args.endpoints {
+        // show working endpoints in plain text format, in order of the list
+        let statuses = checker.run(networks, |_, _| {}).await;
+        for status in statuses.iter().filter(|x| x.is_ok()) {
+            println!("{}", status.endpoint);
+        }
+        return Ok(());
+    }
+
+    let single = !args.network.is_empty();
+    let json = args.json;
+    checker
+        .run(networks, |network, status| {
+            if json {
+                status.print_json();
+            } else if single {
+                status.log();
+            } else {
+                status.log_with_address(network.label());
+            }
+        })
+        .await;
+    if let Ok(report) = serde_json::to_string(&cache::shared().report()) {
+        tracing::debug!("cache {}", report);
     }
     Ok(())
 }
@@ -1,88 +1,283 @@
-use std::collections::HashSet;
+use crate::policy::Policy;
+use crate::rpc::{RetryOn, RetryPolicy, RpcConfig};
+use crate::tags::TagExpr;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read};
 use std::iter::FromIterator;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
-pub struct Network {
-    pub tags: HashSet<String>,
-    pub endpoint: String,
-}
+/// Read timeout of JSON-RPC calls when the endpoint doesn't override it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(25);
+/// Connect timeout of JSON-RPC calls when the endpoint doesn't override it
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Debug, Clone)]
-pub enum TagMatcher {
-    Has(String),
-    DoesntHave(String),
+/// Retry policy of JSON-RPC calls, values that are not set are taken from the defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Retry {
+    /// Number of retries after the failed attempt (default: 2)
+    pub count: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled for every next one (default: 250)
+    pub backoff_ms: Option<u64>,
+    /// Limit of the delay between retries in milliseconds (default: 5000)
+    pub max_backoff_ms: Option<u64>,
+    /// Kinds of failures to retry: transport, timeout, http, rpc, decode (default: transport)
+    pub on: Option<Vec<RetryOn>>,
 }
 
-impl TagMatcher {
-    pub fn from(src: &str) -> Option<Self> {
-        if src.len() == 0 {
-            return None;
+impl Retry {
+    /// Fills values that are not set with the values from `other`
+    pub fn or(self, other: &Retry) -> Self {
+        Self {
+            count: self.count.or(other.count),
+            backoff_ms: self.backoff_ms.or(other.backoff_ms),
+            max_backoff_ms: self.max_backoff_ms.or(other.max_backoff_ms),
+            on: self.on.or_else(|| other.on.clone()),
         }
-        if src.len() > 1 {
-            let first_ch: String = src.chars().take(1).collect();
-            if first_ch == "-" {
-                let res = src.chars().skip(1).collect();
-                return Some(Self::DoesntHave(res));
-            }
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            count: self.count.unwrap_or(default.count),
+            backoff: self
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.backoff),
+            max_backoff: self
+                .max_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_backoff),
+            on: self.on.clone().unwrap_or(default.on),
         }
-        Some(Self::Has(src.to_string()))
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Thresholds {
+    /// Head block below this height is reported as a warning (1 if not set)
+    pub min_head_block: Option<u64>,
+    /// Age of the head block in seconds, after which the endpoint is reported as a warning
+    pub head_age_warn: Option<u64>,
+    /// Age of the head block in seconds, after which the endpoint is reported as failed
+    pub head_age_fail: Option<u64>,
+    /// Number of peers below which the endpoint is reported as a warning (1 if not set)
+    pub min_peers: Option<u64>,
+    /// Seconds between `newHeads` notifications of WebSocket endpoint to report a warning
+    pub new_heads_warn: Option<u64>,
+    /// Seconds between `newHeads` notifications of WebSocket endpoint to report a failure
+    pub new_heads_fail: Option<u64>,
+    /// Average latency of the latest RPC calls in milliseconds to report a warning
+    pub latency_warn: Option<u64>,
+}
+
+impl Thresholds {
+    /// Fills values that are not set with the values from `other`
+    pub fn or(self, other: &Thresholds) -> Self {
+        Self {
+            min_head_block: self.min_head_block.or(other.min_head_block),
+            head_age_warn: self.head_age_warn.or(other.head_age_warn),
+            head_age_fail: self.head_age_fail.or(other.head_age_fail),
+            min_peers: self.min_peers.or(other.min_peers),
+            new_heads_warn: self.new_heads_warn.or(other.new_heads_warn),
+            new_heads_fail: self.new_heads_fail.or(other.new_heads_fail),
+            latency_warn: self.latency_warn.or(other.latency_warn),
+        }
+    }
+}
+
+/// Settings shared by several networks, in `[defaults]` and `[tags.<tag>]` sections
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub timeout: Option<u64>,
+    pub connect_timeout: Option<u64>,
+    pub deadline: Option<u64>,
+    /// Whether results of the probes are cached (default: true)
+    pub cache: Option<bool>,
+    #[serde(default)]
+    pub retry: Retry,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    #[serde(default)]
+    pub tags: HashSet<String>,
+    pub endpoint: String,
+    /// Display name of the endpoint, used in logs instead of the address
+    pub name: Option<String>,
+    /// Chain id the endpoint is expected to serve
+    pub chain_id: Option<u64>,
+    /// Network id (`net_version`) of the endpoint, when it differs from the chain id
+    pub network_id: Option<u64>,
+    /// Read timeout of JSON-RPC calls, in seconds
+    pub timeout: Option<u64>,
+    /// Connect timeout of WebSocket JSON-RPC calls, in seconds
+    pub connect_timeout: Option<u64>,
+    /// Time limit of JSON-RPC call including its retries, in seconds
+    pub deadline: Option<u64>,
+    /// Whether results of the probes are cached, true if not set
+    pub cache: Option<bool>,
+    #[serde(default)]
+    pub retry: Retry,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+    /// Requirements from the `[policy]` section of the networks file
+    #[serde(skip)]
+    pub policy: Arc<Policy>,
+}
+
 impl Network {
     pub fn new(endpoint: &str, tags: HashSet<String>) -> Self {
         Self {
             endpoint: endpoint.to_string(),
-            tags: tags.clone(),
+            tags,
+            name: None,
+            chain_id: None,
+            network_id: None,
+            timeout: None,
+            connect_timeout: None,
+            deadline: None,
+            cache: None,
+            retry: Retry::default(),
+            thresholds: Thresholds::default(),
+            policy: Arc::default(),
+        }
+    }
+
+    /// Name of the endpoint to be displayed in logs
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.endpoint)
+    }
+
+    /// Timeouts and retries of JSON-RPC calls to the endpoint
+    pub fn rpc_config(&self) -> RpcConfig {
+        RpcConfig {
+            connect_timeout: self
+                .connect_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: self
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TIMEOUT),
+            deadline: self.deadline.map(Duration::from_secs),
+            retry: self.retry.policy(),
         }
     }
-    pub fn has_all(&self, tags: &HashSet<String>) -> bool {
-        if tags.len() > 0 {
-            for t in tags {
-                if let Some(tm) = TagMatcher::from(&t) {
-                    if match tm {
-                        TagMatcher::Has(x) => !self.tags.contains(&x),
-                        TagMatcher::DoesntHave(x) => self.tags.contains(&x),
-                    } {
-                        return false;
-                    }
+
+    /// Fills settings that are not set for the network itself with the values from `settings`
+    pub fn inherit(&mut self, settings: &Settings) {
+        self.timeout = self.timeout.or(settings.timeout);
+        self.connect_timeout = self.connect_timeout.or(settings.connect_timeout);
+        self.deadline = self.deadline.or(settings.deadline);
+        self.cache = self.cache.or(settings.cache);
+        self.retry = self.retry.clone().or(&settings.retry);
+        self.thresholds = self.thresholds.clone().or(&settings.thresholds);
+    }
+
+    /// Whether the network tags satisfy the filter expression
+    pub fn matches(&self, filter: &TagExpr) -> bool {
+        filter.matches(&self.tags)
+    }
+}
+
+/// Structured networks file, as it is stored in TOML or JSON
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworksFile {
+    #[serde(default)]
+    defaults: Settings,
+    #[serde(default)]
+    tags: BTreeMap<String, Settings>,
+    #[serde(default)]
+    policy: Policy,
+    #[serde(default)]
+    networks: Vec<Network>,
+}
+
+impl NetworksFile {
+    /// Networks with settings inherited from their tags and defaults.
+    /// Own settings of the network take precedence, then tags in alphabetical order
+    fn resolve(self) -> Vec<Network> {
+        let policy = Arc::new(self.policy);
+        let mut networks = self.networks;
+        for network in networks.iter_mut() {
+            network.policy = Arc::clone(&policy);
+            for (tag, settings) in &self.tags {
+                if network.tags.contains(tag) {
+                    network.inherit(settings);
                 }
             }
+            network.inherit(&self.defaults);
         }
-        return true;
+        networks
     }
 }
 
 pub fn from_reader(reader: impl BufRead) -> anyhow::Result<Vec<Network>> {
     let mut tags: HashSet<String> = HashSet::new();
-    let lines: Vec<Network> = reader
-        .lines()
-        .map(|row| row.unwrap())
-        .filter(|row| row.trim().len() > 0)
-        .map(|row| {
-            let start = row.trim().chars().take(1).collect::<String>();
-            if start == "#" {
-                let remainder = row.trim().chars().skip(1).collect::<String>();
-                let iter = remainder.split(",").map(|x| x.trim().to_string());
-                tags = HashSet::from_iter(iter);
-                None
-            } else {
-                let t = tags.clone();
-                tags = HashSet::new();
-                Some(Network::new(&row.trim(), t))
-            }
-        })
-        .flatten()
-        .collect();
+    let mut lines: Vec<Network> = vec![];
+    for row in reader.lines() {
+        let row = row?;
+        if row.trim().is_empty() {
+            continue;
+        }
+        let start = row.trim().chars().take(1).collect::<String>();
+        if start == "#" {
+            let remainder = row.trim().chars().skip(1).collect::<String>();
+            let iter = remainder.split(',').map(|x| x.trim().to_string());
+            tags = HashSet::from_iter(iter);
+        } else {
+            let t = tags.clone();
+            tags = HashSet::new();
+            lines.push(Network::new(row.trim(), t));
+        }
+    }
     Ok(lines)
 }
 
+pub fn from_toml(source: &str) -> anyhow::Result<Vec<Network>> {
+    let file: NetworksFile = toml::from_str(source)?;
+    Ok(file.resolve())
+}
+
+pub fn from_json(source: &str) -> anyhow::Result<Vec<Network>> {
+    let file: NetworksFile = serde_json::from_str(source)?;
+    Ok(file.resolve())
+}
+
+/// Reads the list of networks. Format is detected by file extension:
+/// `.toml` and `.json` are structured, anything else is a plain text list
 pub fn from_file(source: &str) -> anyhow::Result<Vec<Network>> {
-    let file = File::open(Path::new(source)).unwrap();
-    return from_reader(io::BufReader::new(file));
+    let path = Path::new(source);
+    let mut file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open networks file {}: {}", source, e))?;
+    let ext = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+    match ext.as_deref() {
+        Some("toml") | Some("json") => {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            let res = if ext.as_deref() == Some("toml") {
+                from_toml(&contents)
+            } else {
+                from_json(&contents)
+            };
+            res.map_err(|e| anyhow::anyhow!("failed to parse networks file {}: {}", source, e))
+        }
+        _ => from_reader(io::BufReader::new(file)),
+    }
 }
 
 #[cfg(test)]
@@ -130,18 +325,153 @@ mod tests {
         assert_eq!(output[2].tags, tags3);
     }
 
+    #[test]
+    pub fn it_reads_toml() {
+        let input = r#"
+        [[networks]]
+        endpoint = "https://rpc.gnosischain.com/"
+        name = "xdai"
+        tags = ["xdai", "nosync"]
+        chain_id = 100
+        timeout = 10
+        thresholds = { min_head_block = 1000 }
+
+        [[networks]]
+        endpoint = "https://public-node.rsk.co"
+        "#;
+        let output = from_toml(input).unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].label(), "xdai");
+        assert!(output[0].tags.contains("nosync"));
+        assert_eq!(output[0].chain_id, Some(100));
+        assert_eq!(output[0].rpc_config().read_timeout, Duration::from_secs(10));
+        assert_eq!(output[0].thresholds.min_head_block, Some(1000));
+        assert_eq!(output[1].label(), "https://public-node.rsk.co");
+        assert_eq!(output[1].tags, HashSet::new());
+        assert_eq!(output[1].rpc_config().read_timeout, DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    pub fn it_inherits_settings() {
+        let input = r#"
+        [defaults]
+        timeout = 5
+        thresholds = { head_age_warn = 60, head_age_fail = 600 }
+
+        [tags.rsk.thresholds]
+        head_age_warn = 120
+        min_peers = 5
+        latency_warn = 1500
+
+        [tags.testnet]
+        timeout = 50
+
+        [[networks]]
+        endpoint = "https://public-node.rsk.co"
+        tags = ["rsk"]
+
+        [[networks]]
+        endpoint = "https://public-node.testnet.rsk.co"
+        tags = ["rsk", "testnet"]
+        thresholds = { head_age_fail = 3600 }
+        "#;
+        let output = from_toml(input).unwrap();
+        assert_eq!(output[0].rpc_config().read_timeout, Duration::from_secs(5));
+        assert_eq!(output[0].thresholds.head_age_warn, Some(120));
+        assert_eq!(output[0].thresholds.head_age_fail, Some(600));
+        assert_eq!(output[1].rpc_config().read_timeout, Duration::from_secs(50));
+        assert_eq!(output[1].thresholds.head_age_warn, Some(120));
+        assert_eq!(output[1].thresholds.head_age_fail, Some(3600));
+        assert_eq!(output[1].thresholds.min_peers, Some(5));
+        assert_eq!(output[1].thresholds.latency_warn, Some(1500));
+    }
+
+    #[test]
+    pub fn it_inherits_retry_policy() {
+        let input = r#"
+        [defaults]
+        connect_timeout = 3
+        retry = { count = 3, backoff_ms = 100 }
+
+        [tags.flaky]
+        deadline = 60
+        retry = { count = 5, on = ["transport", "timeout"] }
+
+        [[networks]]
+        endpoint = "https://public-node.rsk.co"
+
+        [[networks]]
+        endpoint = "https://public-node.testnet.rsk.co"
+        tags = ["flaky"]
+        retry = { max_backoff_ms = 1000 }
+        "#;
+        let output = from_toml(input).unwrap();
+        let config = output[0].rpc_config();
+        assert_eq!(config.connect_timeout, Duration::from_secs(3));
+        assert_eq!(config.deadline, None);
+        assert_eq!(config.retry.count, 3);
+        assert_eq!(config.retry.backoff, Duration::from_millis(100));
+        assert_eq!(config.retry.on, vec![RetryOn::Transport]);
+        let config = output[1].rpc_config();
+        assert_eq!(config.deadline, Some(Duration::from_secs(60)));
+        assert_eq!(config.retry.count, 5);
+        assert_eq!(config.retry.backoff, Duration::from_millis(100));
+        assert_eq!(config.retry.max_backoff, Duration::from_secs(1));
+        assert_eq!(config.retry.on, vec![RetryOn::Transport, RetryOn::Timeout]);
+    }
+
+    #[test]
+    pub fn it_reads_policy() {
+        let input = r#"
+        [[policy.min_version]]
+        client = "geth"
+        chain_id = 1
+        version = "1.13.12"
+
+        [[networks]]
+        endpoint = "http://localhost:8545"
+        "#;
+        let output = from_toml(input).unwrap();
+        assert_eq!(output[0].policy.min_version.len(), 1);
+        assert_eq!(output[0].policy.min_version[0].chain_id, Some(1));
+    }
+
+    #[test]
+    pub fn it_reads_json() {
+        let input = r#"{"networks": [
+            {"endpoint": "https://public-node.rsk.co", "tags": ["rsk"], "chain_id": 30}
+        ]}"#;
+        let output = from_json(input).unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].endpoint, "https://public-node.rsk.co");
+        assert!(output[0].tags.contains("rsk"));
+        assert_eq!(output[0].chain_id, Some(30));
+        assert!(output[0].name.is_none());
+    }
+
+    #[test]
+    pub fn it_rejects_unknown_fields() {
+        let input = r#"
+        [[networks]]
+        endpoint = "https://public-node.rsk.co"
+        chainid = 30
+        "#;
+        assert!(from_toml(input).is_err());
+    }
+
     #[test]
     pub fn it_matches_tags() {
         let mut tags = HashSet::new();
         tags.insert("one".to_string());
         tags.insert("two".to_string());
         let n = Network::new("test", tags.clone());
-        assert_eq!(n.has_all(&tags), true);
+        let filter = TagExpr::parse("one,two").unwrap();
+        assert!(n.matches(&filter));
 
         let mut t2 = tags.clone();
         t2.remove("two");
         let n2 = Network::new("test", t2.clone());
-        assert_eq!(n2.has_all(&tags), false);
+        assert!(!n2.matches(&filter));
     }
 
     #[test]
@@ -151,9 +481,18 @@ mod tests {
         tags.insert("two".to_string());
         let n = Network::new("test", tags.clone());
 
-        let mut t = HashSet::new();
-        t.insert("one".to_string());
-        t.insert("-three".to_string());
-        assert_eq!(n.has_all(&t), true);
+        let filter = TagExpr::parse("one,-three").unwrap();
+        assert!(n.matches(&filter));
+    }
+
+    #[test]
+    pub fn it_matches_tag_expressions() {
+        let mut tags = HashSet::new();
+        tags.insert("rsk".to_string());
+        tags.insert("testnet".to_string());
+        let n = Network::new("test", tags);
+
+        assert!(n.matches(&TagExpr::parse("rsk or avalanche").unwrap()));
+        assert!(!n.matches(&TagExpr::parse("(rsk or avalanche) and not testnet").unwrap()));
     }
 }
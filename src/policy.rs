@@ -0,0 +1,82 @@
+use crate::client::{ClientInfo, ClientKind, Version};
+use serde::Deserialize;
+
+/// Minimum release of the client family, i.e. for hard-fork readiness
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionRule {
+    pub client: ClientKind,
+    /// Chain the rule applies to, any chain if not set
+    pub chain_id: Option<u64>,
+    pub version: Version,
+}
+
+/// Requirements for the nodes, declared in `[policy]` section of the networks file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub min_version: Vec<VersionRule>,
+}
+
+impl Policy {
+    /// Minimum version required for the client on the chain,
+    /// the highest one of the matching rules
+    pub fn min_version(&self, kind: ClientKind, chain_id: u64) -> Option<Version> {
+        self.min_version
+            .iter()
+            .filter(|x| x.client == kind)
+            .filter(|x| x.chain_id.map(|id| id == chain_id).unwrap_or(true))
+            .map(|x| x.version)
+            .max()
+    }
+
+    /// Reason why the client doesn't satisfy the policy, if it doesn't
+    pub fn check(&self, client: &ClientInfo, chain_id: u64) -> Option<String> {
+        let required = self.min_version(client.kind, chain_id)?;
+        match client.version() {
+            Some(v) if v >= required => None,
+            Some(v) => Some(format!("version {} is below required {}", v, required)),
+            None => Some(format!("version is unknown, required {}", required)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_checks_min_version() {
+        let policy: Policy = toml::from_str(
+            r#"
+            [[min_version]]
+            client = "geth"
+            version = "1.10.8"
+
+            [[min_version]]
+            client = "geth"
+            chain_id = 1
+            version = "1.13.12"
+
+            [[min_version]]
+            client = "nethermind"
+            version = "v1.25.0"
+            "#,
+        )
+        .unwrap();
+        let geth = ClientInfo::parse("Geth/v1.11.6-stable-ea9e62ca/linux-amd64/go1.20.3");
+        assert_eq!(policy.check(&geth, 100), None);
+        assert_eq!(
+            policy.check(&geth, 1),
+            Some("version 1.11.6 is below required 1.13.12".to_owned())
+        );
+        let erigon = ClientInfo::parse("erigon/2.48.1/linux-amd64/go1.20.5");
+        assert_eq!(policy.check(&erigon, 1), None);
+        let nethermind = ClientInfo::parse("Nethermind/unknown");
+        assert_eq!(
+            policy.check(&nethermind, 1),
+            Some("version is unknown, required 1.25.0".to_owned())
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use crate::chainstate::{RpcResponseBlockInfo, RpcResponseBlockReceiptsInfo};
+use crate::rpc::{decode, to_u64, RpcClient, RpcError};
+use ethereum_types::{H256, U64};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Time the strategy that works for the endpoint is kept, before the others are tried again
+pub const STRATEGY_TTL: Duration = Duration::from_secs(300);
+
+static STRATEGIES: OnceLock<Mutex<HashMap<String, (ReceiptsStrategy, Instant)>>> = OnceLock::new();
+
+/// Way of fetching receipts of the block, in order they are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptsStrategy {
+    /// `parity_getBlockReceipts` of OpenEthereum, Nethermind and Erigon
+    Parity,
+    /// `eth_getBlockReceipts` of the recent geth and most of the providers
+    Eth,
+    /// `eth_getTransactionReceipt` for every transaction of the block
+    PerTransaction,
+}
+
+impl ReceiptsStrategy {
+    pub fn method(self) -> &'static str {
+        match self {
+            Self::Parity => "parity_getBlockReceipts",
+            Self::Eth => "eth_getBlockReceipts",
+            Self::PerTransaction => "eth_getTransactionReceipt",
+        }
+    }
+
+    /// Strategy to try when the endpoint doesn't support this one
+    pub fn next(self) -> Option<Self> {
+        match self {
+            Self::Parity => Some(Self::Eth),
+            Self::Eth => Some(Self::PerTransaction),
+            Self::PerTransaction => None,
+        }
+    }
+
+    /// Whether all receipts of the block are returned by a single call
+    pub fn is_block_level(self) -> bool {
+        self != Self::PerTransaction
+    }
+
+    async fn fetch(
+        self,
+        client: &RpcClient,
+        block: &RpcResponseBlockInfo,
+    ) -> Result<Vec<RpcResponseBlockReceiptsInfo>, RpcError> {
+        let number = U64::from(to_u64("block number", block.number)?);
+        match self {
+            Self::Parity | Self::Eth => client.call(self.method(), [number]).await,
+            Self::PerTransaction => get_transaction_receipts(client, &block.tx_hashes()).await,
+        }
+    }
+}
+
+/// Strategy of the endpoint that is known to work, or the first one to try.
+/// Strategies are kept per endpoint apart from the probe cache, so they are kept
+/// with caching disabled too, and the unsupported ones are not tried again until it expires
+pub fn strategy(endpoint: &str) -> ReceiptsStrategy {
+    STRATEGIES
+        .get()
+        .and_then(|x| x.lock().ok()?.get(endpoint).copied())
+        .filter(|(_, expires)| *expires > Instant::now())
+        .map(|(strategy, _)| strategy)
+        .unwrap_or(ReceiptsStrategy::Parity)
+}
+
+fn set_strategy(endpoint: &str, strategy: ReceiptsStrategy) {
+    let strategies = STRATEGIES.get_or_init(Default::default);
+    if let Ok(mut strategies) = strategies.lock() {
+        let now = Instant::now();
+        strategies.retain(|_, (_, expires)| *expires > now);
+        strategies.insert(endpoint.to_owned(), (strategy, now + STRATEGY_TTL));
+    }
+}
+
+/// Receipts of the block, with the strategy of the endpoint.
+/// Strategies that are not supported by the endpoint are skipped in favor of the next ones
+pub async fn get_block_receipts(
+    client: &RpcClient,
+    block: &RpcResponseBlockInfo,
+) -> Result<Vec<RpcResponseBlockReceiptsInfo>, RpcError> {
+    let mut current = strategy(client.endpoint());
+    loop {
+        match current.fetch(client, block).await {
+            Ok(x) => {
+                set_strategy(client.endpoint(), current);
+                return Ok(x);
+            }
+            Err(e) if e.is_unsupported_method() => match current.next() {
+                Some(next) => {
+                    tracing::debug!("{}: {}, trying {}", client.endpoint(), e, next.method());
+                    set_strategy(client.endpoint(), next);
+                    current = next;
+                }
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Receipts of the block from the result of `strategy` method, that was sent in a batch.
+/// When the endpoint doesn't support it, receipts are fetched with the next strategies
+pub async fn from_batch(
+    client: &RpcClient,
+    block: &RpcResponseBlockInfo,
+    strategy: ReceiptsStrategy,
+    result: Result<serde_json::Value, RpcError>,
+) -> Result<Vec<RpcResponseBlockReceiptsInfo>, RpcError> {
+    match decode(strategy.method(), result) {
+        Ok(x) => {
+            set_strategy(client.endpoint(), strategy);
+            Ok(x)
+        }
+        Err(e) if e.is_unsupported_method() => match strategy.next() {
+            Some(next) => {
+                set_strategy(client.endpoint(), next);
+                get_block_receipts(client, block).await
+            }
+            None => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Receipts of the transactions, in a single batch unless the endpoint rejects batches
+async fn get_transaction_receipts(
+    client: &RpcClient,
+    hashes: &[H256],
+) -> Result<Vec<RpcResponseBlockReceiptsInfo>, RpcError> {
+    let method = ReceiptsStrategy::PerTransaction.method();
+    if hashes.is_empty() {
+        return Ok(vec![]);
+    }
+    let calls: Vec<(&str, serde_json::Value)> = hashes
+        .iter()
+        .map(|x| (method, serde_json::json!([x])))
+        .collect();
+    let receipts: Vec<Option<RpcResponseBlockReceiptsInfo>> = match client.batch(&calls).await {
+        Ok(results) => results
+            .into_iter()
+            .map(|x| decode(method, x))
+            .collect::<Result<_, _>>()?,
+        Err(RpcError::BatchRejected(_)) => {
+            let mut receipts = vec![];
+            for hash in hashes {
+                receipts.push(client.call(method, [hash]).await?);
+            }
+            receipts
+        }
+        Err(e) => return Err(e),
+    };
+    receipts
+        .into_iter()
+        .zip(hashes)
+        .map(|(receipt, hash)| {
+            receipt.ok_or_else(|| RpcError::Decode(format!("receipt of {:?} is not found", hash)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{self, CacheConfig};
+    use crate::chainstate::BlockTransaction;
+    use crate::rpc::{RpcConfig, Transport};
+    use async_std::task;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const RECEIPT: &str = r#"{"blockHash":"0xa917fcc721a5465a484e9be17cda0cc5493933dd3bc70c9adbee192cb419c9d7","blockNumber":"0x1","contractAddress":null,"cumulativeGasUsed":"0x5208","effectiveGasPrice":"0x3b9aca00","from":"0x00000000000000000000000000000000000000aa","gasUsed":"0x5208","logs":[],"status":"0x1","to":"0x00000000000000000000000000000000000000bb","transactionHash":"0x0000000000000000000000000000000000000000000000000000000000000001","transactionIndex":"0x0"}"#;
+
+    /// Node supporting only `eth_getTransactionReceipt`, counting the calls of every method.
+    /// Batches are answered unless they are rejected or time out
+    #[derive(Debug, Default)]
+    struct Geth {
+        calls: Mutex<HashMap<String, u32>>,
+        reject_batches: bool,
+        batch_timeout: bool,
+    }
+
+    impl Geth {
+        fn answer(&self, request: &serde_json::Value) -> serde_json::Value {
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            *self
+                .calls
+                .lock()
+                .unwrap()
+                .entry(method.clone())
+                .or_default() += 1;
+            let id = request["id"].clone();
+            if method == "eth_getTransactionReceipt" {
+                let result: serde_json::Value = serde_json::from_str(RECEIPT).unwrap();
+                serde_json::json!({"jsonrpc": "2.0", "id": id, "result": result})
+            } else {
+                let error =
+                    serde_json::json!({"code": -32601, "message": "the method does not exist"});
+                serde_json::json!({"jsonrpc": "2.0", "id": id, "error": error})
+            }
+        }
+
+        fn calls(&self, method: &str) -> u32 {
+            self.calls.lock().unwrap().get(method).copied().unwrap_or(0)
+        }
+    }
+
+    #[async_trait]
+    impl Transport for Arc<Geth> {
+        async fn send(&self, payload: &str, _timeout: Duration) -> Result<String, RpcError> {
+            let request: serde_json::Value = serde_json::from_str(payload).unwrap();
+            let response = match request.as_array() {
+                Some(_) if self.batch_timeout => {
+                    return Err(RpcError::Timeout("timed out reading response".to_owned()))
+                }
+                Some(_) if self.reject_batches => {
+                    let error = serde_json::json!({"code": -32600, "message": "batch requests are not supported"});
+                    serde_json::json!({"jsonrpc": "2.0", "id": null, "error": error})
+                }
+                Some(batch) => batch.iter().map(|x| self.answer(x)).collect(),
+                None => self.answer(&request),
+            };
+            Ok(response.to_string())
+        }
+    }
+
+    fn block(transactions: Vec<H256>) -> RpcResponseBlockInfo {
+        let mut block: RpcResponseBlockInfo = serde_json::from_value(serde_json::json!({
+            "difficulty": "0x0", "gasLimit": "0x1c9c380", "gasUsed": "0x5208",
+            "hash": "0xa917fcc721a5465a484e9be17cda0cc5493933dd3bc70c9adbee192cb419c9d7",
+            "miner": "0x0000000000000000000000000000000000000000",
+            "number": "0x1", "size": "0x220", "timestamp": "0x0",
+            "totalDifficulty": "0x0", "transactions": []
+        }))
+        .unwrap();
+        block.transactions = transactions
+            .into_iter()
+            .map(BlockTransaction::Hash)
+            .collect();
+        block
+    }
+
+    #[test]
+    pub fn it_orders_strategies() {
+        assert_eq!(ReceiptsStrategy::Parity.next(), Some(ReceiptsStrategy::Eth));
+        assert_eq!(
+            ReceiptsStrategy::Eth.next(),
+            Some(ReceiptsStrategy::PerTransaction)
+        );
+        assert_eq!(ReceiptsStrategy::PerTransaction.next(), None);
+        assert_eq!(strategy("http://unknown"), ReceiptsStrategy::Parity);
+    }
+
+    #[test]
+    pub fn it_falls_back_to_transaction_receipts() {
+        let geth = Arc::new(Geth::default());
+        let endpoint = "http://geth-receipts";
+        let client =
+            RpcClient::with_transport(endpoint, &RpcConfig::default(), Arc::new(geth.clone()));
+        let block = block(vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]);
+
+        let receipts = task::block_on(get_block_receipts(&client, &block)).unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(strategy(endpoint), ReceiptsStrategy::PerTransaction);
+        assert_eq!(geth.calls("parity_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getTransactionReceipt"), 2);
+
+        // unsupported methods are not called again
+        task::block_on(get_block_receipts(&client, &block)).unwrap();
+        assert_eq!(geth.calls("parity_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getTransactionReceipt"), 4);
+    }
+
+    #[test]
+    pub fn it_keeps_strategies_with_caching_disabled() {
+        let endpoint = "http://geth-no-cache";
+        let mut config = CacheConfig::default();
+        config.disabled.insert(endpoint.to_owned());
+        cache::configure(config);
+
+        let geth = Arc::new(Geth::default());
+        let client =
+            RpcClient::with_transport(endpoint, &RpcConfig::default(), Arc::new(geth.clone()));
+        let block = block(vec![H256::from_low_u64_be(1)]);
+        task::block_on(get_block_receipts(&client, &block)).unwrap();
+        task::block_on(get_block_receipts(&client, &block)).unwrap();
+        assert_eq!(strategy(endpoint), ReceiptsStrategy::PerTransaction);
+        assert_eq!(geth.calls("parity_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getBlockReceipts"), 1);
+        assert_eq!(geth.calls("eth_getTransactionReceipt"), 2);
+    }
+
+    #[test]
+    pub fn it_fetches_transaction_receipts_one_by_one_when_batches_are_rejected() {
+        let geth = Arc::new(Geth {
+            reject_batches: true,
+            ..Default::default()
+        });
+        let client = RpcClient::with_transport(
+            "http://geth-no-batches",
+            &RpcConfig::default(),
+            Arc::new(geth.clone()),
+        );
+        let hashes = [H256::from_low_u64_be(1), H256::from_low_u64_be(2)];
+        let receipts = task::block_on(get_transaction_receipts(&client, &hashes)).unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(geth.calls("eth_getTransactionReceipt"), 2);
+    }
+
+    #[test]
+    pub fn it_returns_failed_batch_of_transaction_receipts() {
+        let geth = Arc::new(Geth {
+            batch_timeout: true,
+            ..Default::default()
+        });
+        let client = RpcClient::with_transport(
+            "http://geth-slow-batches",
+            &RpcConfig::default(),
+            Arc::new(geth.clone()),
+        );
+        let hashes = [H256::from_low_u64_be(1), H256::from_low_u64_be(2)];
+        let out = task::block_on(get_transaction_receipts(&client, &hashes));
+        assert!(matches!(out, Err(RpcError::Timeout(_))));
+        // the batch is not retried one transaction at a time
+        assert_eq!(geth.calls("eth_getTransactionReceipt"), 0);
+    }
+}
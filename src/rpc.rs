@@ -0,0 +1,1229 @@
+use crate::network::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::{ipc, ws};
+use async_std::{future, task};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Ids of JSON-RPC requests, unique within the process
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Error object of JSON-RPC response
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    /// Request could not be sent or response could not be received
+    Transport(String),
+    /// Endpoint didn't connect or respond in time
+    Timeout(String),
+    /// Endpoint responded with HTTP error status and the body that is not JSON-RPC
+    HttpStatus { status: u16, body: String },
+    /// Node responded with JSON-RPC error
+    Rpc { code: i64, message: String },
+    /// Node doesn't support the requested method
+    UnsupportedMethod { code: i64, message: String },
+    /// Response is not a valid JSON-RPC response or doesn't have the expected type
+    Decode(String),
+    /// Response id doesn't match the id of the request
+    IdMismatch {
+        expected: u64,
+        got: serde_json::Value,
+    },
+    /// Endpoint doesn't accept batches and responded with a single response
+    BatchRejected(String),
+}
+
+impl RpcError {
+    /// Error from JSON-RPC error object. Nodes report unsupported methods
+    /// with the standard code or with their own code and message
+    pub fn rpc(code: i64, message: String) -> Self {
+        let msg = message.to_lowercase();
+        let unsupported = code == -32601
+            || (msg.contains("method")
+                && (msg.contains("not found")
+                    || msg.contains("not supported")
+                    || msg.contains("does not exist")
+                    || msg.contains("not available")));
+        if unsupported {
+            Self::UnsupportedMethod { code, message }
+        } else {
+            Self::Rpc { code, message }
+        }
+    }
+
+    /// Whether the node doesn't support the requested method
+    pub fn is_unsupported_method(&self) -> bool {
+        matches!(self, Self::UnsupportedMethod { .. })
+    }
+}
+
+/// Length of the body of HTTP error response kept in the error
+const MAX_ERROR_BODY: usize = 200;
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(msg) => write!(f, "{}", msg),
+            Self::Timeout(msg) => write!(f, "{}", msg),
+            Self::HttpStatus { status, body } => write!(f, "HTTP status {}: {}", status, body),
+            Self::Rpc { message, .. } => write!(f, "{}", message),
+            Self::UnsupportedMethod { message, .. } => write!(f, "{}", message),
+            Self::Decode(msg) => write!(f, "{}", msg),
+            Self::IdMismatch { expected, got } => {
+                write!(
+                    f,
+                    "response id {} doesn't match request id {}",
+                    got, expected
+                )
+            }
+            Self::BatchRejected(msg) => write!(f, "batch is rejected: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Error of the socket I/O, telling timeouts from other failures
+pub fn io_error(err: io::Error) -> RpcError {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => RpcError::Timeout(err.to_string()),
+        _ => RpcError::Transport(err.to_string()),
+    }
+}
+
+/// Kind of failed calls that may be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryOn {
+    /// Connection refused or reset, name resolution failures
+    Transport,
+    /// Connect or read timeouts
+    Timeout,
+    /// HTTP error statuses, i.e. 502 or 429 of the proxy in front of the node
+    Http,
+    /// JSON-RPC errors, except unsupported methods
+    Rpc,
+    /// Responses that are not valid JSON-RPC, i.e. error pages of proxies
+    Decode,
+}
+
+/// How failed calls are retried: `count` more attempts,
+/// with the delay doubling from `backoff` up to `max_backoff`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    pub count: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub on: Vec<RetryOn>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            count: 2,
+            backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            on: vec![RetryOn::Transport],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the retry that follows the given (zero-based) attempt
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    pub fn is_retryable(&self, err: &RpcError) -> bool {
+        let kind = match err {
+            RpcError::Transport(_) => RetryOn::Transport,
+            RpcError::Timeout(_) => RetryOn::Timeout,
+            RpcError::HttpStatus { .. } => RetryOn::Http,
+            RpcError::Rpc { .. } => RetryOn::Rpc,
+            RpcError::UnsupportedMethod { .. } | RpcError::BatchRejected(_) => return false,
+            RpcError::Decode(_) | RpcError::IdMismatch { .. } => RetryOn::Decode,
+        };
+        self.on.contains(&kind)
+    }
+}
+
+/// Timeouts and retries of JSON-RPC calls to the endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RpcConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    /// Time limit of the call, including all of its retries
+    pub deadline: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_TIMEOUT,
+            deadline: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl RpcConfig {
+    /// Read timeout of the single attempt, that doesn't exceed the deadline
+    pub fn attempt_timeout(&self) -> Duration {
+        match self.deadline {
+            Some(deadline) => self.read_timeout.min(deadline),
+            None => self.read_timeout,
+        }
+    }
+
+    /// Time left of the deadline of the call started at the given instant, `None` if it is not set
+    fn remaining(&self, started: Instant) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_sub(started.elapsed()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RpcRequest<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    params: serde_json::Value,
+    id: u64,
+}
+
+/// Successful JSON-RPC response with the result of the known type
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcResponse<T> {
+    pub id: serde_json::Value,
+    pub result: T,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawResponse {
+    #[serde(default)]
+    id: serde_json::Value,
+    result: Option<serde_json::Value>,
+    error: Option<ErrorObject>,
+}
+
+impl RawResponse {
+    fn into_result(self) -> Result<serde_json::Value, RpcError> {
+        if let Some(err) = self.error {
+            return Err(RpcError::rpc(err.code, err.message));
+        }
+        Ok(self.result.unwrap_or(serde_json::Value::Null))
+    }
+}
+
+/// Numeric id of the response. Some nodes return numeric ids as strings
+fn response_id(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.parse::<u64>().ok(),
+        _ => None,
+    }
+}
+
+/// Whether the id of the response is the id of the request
+fn is_same_id(id: u64, value: &serde_json::Value) -> bool {
+    response_id(value) == Some(id)
+}
+
+/// Notification of the subscription, that is not a response to any request
+pub fn is_notification(body: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Envelope {
+        method: Option<String>,
+        id: Option<serde_json::Value>,
+    }
+    match serde_json::from_str::<Envelope>(body) {
+        Ok(x) => x.method.is_some() && x.id.is_none(),
+        Err(_) => false,
+    }
+}
+
+/// Converts result of the call into the expected type
+pub fn decode<R: DeserializeOwned>(
+    method: &str,
+    result: Result<serde_json::Value, RpcError>,
+) -> Result<R, RpcError> {
+    let value = result?;
+    serde_json::from_value(value.clone())
+        .map_err(|e| RpcError::Decode(format!("{} {}. RESULT: {}", method, e, value)))
+}
+
+/// Converts the number from the response, failing when it doesn't fit into `u64`
+pub fn to_u64<T>(name: &str, value: T) -> Result<u64, RpcError>
+where
+    T: TryInto<u64> + fmt::Display + Copy,
+{
+    value
+        .try_into()
+        .map_err(|_| RpcError::Decode(format!("{} is out of range: {}", name, value)))
+}
+
+/// Way of delivering JSON-RPC payloads to the endpoint
+#[async_trait]
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Sends the payload and returns the body of the response,
+    /// which is expected within the timeout
+    async fn send(&self, payload: &str, timeout: Duration) -> Result<String, RpcError>;
+}
+
+/// Error of the exchange that didn't end within the timeout
+pub fn timed_out(timeout: Duration) -> RpcError {
+    RpcError::Timeout(format!("no response in {}ms", timeout.as_millis()))
+}
+
+/// Transport of the endpoint that can't be reached on this platform, failing every request
+#[cfg(not(unix))]
+#[derive(Debug)]
+struct Unavailable(String);
+
+#[cfg(not(unix))]
+#[async_trait]
+impl Transport for Unavailable {
+    async fn send(&self, _payload: &str, _timeout: Duration) -> Result<String, RpcError> {
+        Err(RpcError::Transport(self.0.clone()))
+    }
+}
+
+/// Keep-alive connections kept open for every HTTP endpoint,
+/// enough for the server to handle concurrent requests without reconnecting
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
+/// JSON-RPC over HTTP(S). Connecting is a part of the request,
+/// so it is limited by the timeout of the attempt
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    endpoint: String,
+    client: surf::Client,
+}
+
+impl HttpTransport {
+    pub fn new(endpoint: &str) -> Self {
+        let client = surf::Config::new()
+            .set_timeout(None)
+            .set_max_connections_per_host(MAX_IDLE_CONNECTIONS)
+            .try_into()
+            .expect("HTTP client config is valid");
+        Self {
+            endpoint: endpoint.to_string(),
+            client,
+        }
+    }
+
+    /// Status and body of the response to the payload
+    async fn post(&self, payload: &str) -> Result<(u16, String), RpcError> {
+        let url = surf::Url::parse(&self.endpoint)
+            .map_err(|e| RpcError::Transport(format!("{}: {}", self.endpoint, e)))?;
+        let mut rq = surf::Request::new(surf::http::Method::Post, url);
+        rq.set_body(payload);
+        rq.set_content_type(surf::http::mime::JSON);
+        let mut response = self
+            .client
+            .send(rq)
+            .await
+            .map_err(|e| http_error(&self.endpoint, e))?;
+        let status = u16::from(response.status());
+        let body =
+            response
+                .body_string()
+                .await
+                .map_err(|e| match http_error(&self.endpoint, e) {
+                    RpcError::Timeout(msg) => {
+                        RpcError::Timeout(format!("status {}: {}", status, msg))
+                    }
+                    err => RpcError::Transport(format!("status {}: {}", status, err)),
+                })?;
+        Ok((status, body))
+    }
+}
+
+/// Error of the HTTP request, telling timeouts of the socket from other failures
+fn http_error(endpoint: &str, err: surf::Error) -> RpcError {
+    let err = err.into_inner();
+    let msg = format!("{}: {}", endpoint, err.root_cause());
+    let io = err.chain().find_map(|x| x.downcast_ref::<io::Error>());
+    match io.map(|x| x.kind()) {
+        Some(io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => RpcError::Timeout(msg),
+        _ => RpcError::Transport(msg),
+    }
+}
+
+/// Body of HTTP error response, as nodes put JSON-RPC errors there.
+/// Other bodies, i.e. error pages of proxies, are reported with the status
+fn error_body(status: u16, body: String) -> Result<String, RpcError> {
+    let trimmed = body.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        return Ok(body);
+    }
+    Err(RpcError::HttpStatus {
+        status,
+        body: trimmed.chars().take(MAX_ERROR_BODY).collect(),
+    })
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, payload: &str, timeout: Duration) -> Result<String, RpcError> {
+        let (status, body) = future::timeout(timeout, self.post(payload))
+            .await
+            .map_err(|_| timed_out(timeout))??;
+        if status >= 400 {
+            return error_body(status, body);
+        }
+        Ok(body)
+    }
+}
+
+/// Number of the latest calls the latency is averaged over
+const LATENCY_WINDOW: usize = 20;
+
+/// Latency of the calls of the single method, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MethodLatency {
+    /// Number of calls made by the client
+    pub calls: u64,
+    pub last_ms: f64,
+    /// Average of the latest calls
+    pub avg_ms: f64,
+}
+
+fn millis(d: Duration) -> f64 {
+    (d.as_secs_f64() * 100_000.0).round() / 100.0
+}
+
+/// Durations of the latest calls
+#[derive(Debug, Clone, Default)]
+struct Samples {
+    calls: u64,
+    recent: VecDeque<Duration>,
+}
+
+impl Samples {
+    fn add(&mut self, elapsed: Duration) {
+        self.calls += 1;
+        if self.recent.len() == LATENCY_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(elapsed);
+    }
+
+    fn average(&self) -> Duration {
+        match self.recent.len() {
+            0 => Duration::default(),
+            n => self.recent.iter().sum::<Duration>() / n as u32,
+        }
+    }
+
+    fn summary(&self) -> MethodLatency {
+        MethodLatency {
+            calls: self.calls,
+            last_ms: millis(self.recent.back().copied().unwrap_or_default()),
+            avg_ms: millis(self.average()),
+        }
+    }
+}
+
+/// Latency of the calls to the endpoint, by method and of all of them
+#[derive(Debug, Default)]
+struct LatencyStats {
+    methods: HashMap<String, Samples>,
+    all: Samples,
+}
+
+/// JSON-RPC 2.0 client of the single endpoint.
+/// Transport is chosen by the address: `ws://` and `wss://`, `ipc://` or `.ipc` path, or HTTP
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    endpoint: String,
+    config: RpcConfig,
+    transport: Arc<dyn Transport>,
+    latency: Arc<Mutex<LatencyStats>>,
+    /// Instant no call of the client may run past, i.e. the end of the run
+    until: Option<Instant>,
+}
+
+impl RpcClient {
+    pub fn new(endpoint: &str, config: &RpcConfig) -> Self {
+        let transport: Arc<dyn Transport> = if ws::is_ws(endpoint) {
+            Arc::new(ws::WsTransport::new(endpoint, config))
+        } else if ipc::is_ipc(endpoint) {
+            Self::ipc_transport(endpoint)
+        } else {
+            Arc::new(HttpTransport::new(endpoint))
+        };
+        Self::with_transport(endpoint, config, transport)
+    }
+
+    #[cfg(unix)]
+    fn ipc_transport(endpoint: &str) -> Arc<dyn Transport> {
+        Arc::new(ipc::IpcTransport::new(endpoint))
+    }
+
+    #[cfg(not(unix))]
+    fn ipc_transport(endpoint: &str) -> Arc<dyn Transport> {
+        let reason = format!("{}: IPC endpoints are supported on Unix only", endpoint);
+        Arc::new(Unavailable(reason))
+    }
+
+    /// Client sending the requests over the given transport
+    pub fn with_transport(
+        endpoint: &str,
+        config: &RpcConfig,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            config: config.clone(),
+            transport,
+            latency: Arc::default(),
+            until: None,
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Same client with the latency measured from scratch,
+    /// so the calls of a single check aren't mixed with the earlier ones
+    pub fn measured(&self) -> Self {
+        Self {
+            latency: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Same client with the calls ending by the given instant
+    pub fn until(self, until: Option<Instant>) -> Self {
+        Self { until, ..self }
+    }
+
+    /// Time left until the client has to stop calling, `None` if it is not limited
+    pub fn time_left(&self) -> Option<Duration> {
+        self.until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+    }
+
+    /// Time left of the call started at the given instant, limited by its deadline
+    /// and by the instant of the client
+    fn remaining(&self, started: Instant) -> Option<Duration> {
+        match (self.config.remaining(started), self.time_left()) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        }
+    }
+
+    pub fn config(&self) -> &RpcConfig {
+        &self.config
+    }
+
+    /// Makes the attempts of the call according to the retry policy and the deadlines
+    /// of the client, recording its latency as the time spent in all the attempts
+    pub async fn measure<T, F, Fut>(&self, method: &str, attempt: F) -> Result<T, RpcError>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        let mut spent = Duration::default();
+        let result = self.retry(attempt, &mut spent).await;
+        self.record(method, spent);
+        result
+    }
+
+    /// Records the latency of the call, which is the time spent in all its attempts
+    fn record(&self, method: &str, elapsed: Duration) {
+        let mut stats = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        stats
+            .methods
+            .entry(method.to_string())
+            .or_default()
+            .add(elapsed);
+        stats.all.add(elapsed);
+    }
+
+    /// Latency of the methods called by the client
+    pub fn latency(&self) -> BTreeMap<String, MethodLatency> {
+        let stats = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        stats
+            .methods
+            .iter()
+            .map(|(method, samples)| (method.clone(), samples.summary()))
+            .collect()
+    }
+
+    /// Average latency of the latest calls of any method, with the number of them
+    pub fn average_latency(&self) -> (Duration, usize) {
+        let stats = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        (stats.all.average(), stats.all.recent.len())
+    }
+
+    /// Repeats the attempt according to the retry policy, while the deadline allows.
+    /// Every attempt is given the read timeout, but no more than the time left of the deadline.
+    /// Duration of the attempts is added to `spent`
+    async fn retry<T, F, Fut>(&self, mut attempt: F, spent: &mut Duration) -> Result<T, RpcError>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        let policy = &self.config.retry;
+        let started = Instant::now();
+        let mut n = 0;
+        loop {
+            let timeout = match self.remaining(started) {
+                Some(left) if left.is_zero() => {
+                    return Err(RpcError::Timeout(
+                        "deadline of the call exceeded".to_owned(),
+                    ))
+                }
+                Some(left) => self.config.read_timeout.min(left),
+                None => self.config.read_timeout,
+            };
+            let attempt_started = Instant::now();
+            let result = attempt(timeout).await;
+            *spent += attempt_started.elapsed();
+            let err = match result {
+                Ok(x) => return Ok(x),
+                Err(e) => e,
+            };
+            if n >= policy.count || !policy.is_retryable(&err) {
+                return Err(err);
+            }
+            let delay = policy.delay(n);
+            if let Some(left) = self.remaining(started) {
+                if delay >= left {
+                    return Err(err);
+                }
+            }
+            tracing::debug!("{} retry in {:?}: {}", self.endpoint, delay, err);
+            task::sleep(delay).await;
+            n += 1;
+        }
+    }
+
+    /// Builds request of the method with the given params,
+    /// which should serialize into array or object
+    fn request<'a, P: Serialize>(
+        &self,
+        method: &'a str,
+        params: P,
+    ) -> Result<RpcRequest<'a>, RpcError> {
+        let params = match serde_json::to_value(params) {
+            Ok(serde_json::Value::Null) => serde_json::Value::Array(vec![]),
+            Ok(x) => x,
+            Err(e) => return Err(RpcError::Decode(e.to_string())),
+        };
+        Ok(RpcRequest {
+            jsonrpc: "2.0",
+            method,
+            params,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    /// Serialized request of the method with its id
+    pub fn payload<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<(String, u64), RpcError> {
+        let rq = self.request(method, params)?;
+        let payload = serde_json::to_string(&rq).map_err(|e| RpcError::Decode(e.to_string()))?;
+        Ok((payload, rq.id))
+    }
+
+    /// Result of the method from the body of the response to the request with the given id
+    pub fn response<R: DeserializeOwned>(
+        &self,
+        id: u64,
+        method: &str,
+        body: &str,
+    ) -> Result<R, RpcError> {
+        let response: RawResponse = match serde_json::from_str(body) {
+            Ok(x) => x,
+            Err(e) => return Err(RpcError::Decode(format!("{}. RESPONSE: {}", e, body))),
+        };
+        if response.error.is_none() && !is_same_id(id, &response.id) {
+            return Err(RpcError::IdMismatch {
+                expected: id,
+                got: response.id,
+            });
+        }
+        decode(method, response.into_result())
+    }
+
+    pub async fn call<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<R, RpcError> {
+        let (payload, id) = self.payload(method, params)?;
+        let payload = &payload;
+        self.measure(method, |timeout| async move {
+            let body = self.transport.send(payload, timeout).await?;
+            self.response(id, method, &body)
+        })
+        .await
+    }
+
+    /// Sends the calls in a single JSON-RPC batch, results are returned in the order of calls.
+    /// Error is returned when the batch fails as a whole, `BatchRejected` when the endpoint
+    /// doesn't accept batches
+    pub async fn batch(
+        &self,
+        calls: &[(&str, serde_json::Value)],
+    ) -> Result<Vec<Result<serde_json::Value, RpcError>>, RpcError> {
+        let mut requests = vec![];
+        for (method, params) in calls {
+            requests.push(self.request(method, params)?);
+        }
+        let payload =
+            serde_json::to_string(&requests).map_err(|e| RpcError::Decode(e.to_string()))?;
+        let payload = &payload;
+        let responses: Vec<RawResponse> = self
+            .measure("batch", |timeout| async move {
+                let body = self.transport.send(payload, timeout).await?;
+                match serde_json::from_str(&body) {
+                    Ok(x) => Ok(x),
+                    Err(e) => {
+                        // endpoints without batch support respond with a single response, usually an error
+                        if let Ok(single) = serde_json::from_str::<RawResponse>(&body) {
+                            let reason = match single.error {
+                                Some(err) => err.message,
+                                None => "single response to the batch".to_owned(),
+                            };
+                            return Err(RpcError::BatchRejected(reason));
+                        }
+                        Err(RpcError::Decode(format!("{}. RESPONSE: {}", e, body)))
+                    }
+                }
+            })
+            .await?;
+        // responses may come in any order, they are correlated by id
+        let mut by_id: HashMap<u64, RawResponse> = HashMap::new();
+        for response in responses {
+            if let Some(id) = response_id(&response.id) {
+                by_id.insert(id, response);
+            }
+        }
+        Ok(requests
+            .iter()
+            .map(|rq| match by_id.remove(&rq.id) {
+                Some(response) => response.into_result(),
+                None => Err(RpcError::Decode(format!(
+                    "{}: no response for id {} in batch",
+                    rq.method, rq.id
+                ))),
+            })
+            .collect())
+    }
+
+    /// Same as `call`, but results in `None` when the node doesn't support the method
+    pub async fn call_optional<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<Option<R>, RpcError> {
+        match self.call(method, params).await {
+            Ok(x) => Ok(Some(x)),
+            Err(e) if e.is_unsupported_method() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Long-lived clients, keyed by endpoint and its config,
+/// so keep-alive connections, TLS sessions and sockets are reused between calls
+#[derive(Debug, Default)]
+pub struct ClientPool {
+    clients: Mutex<HashMap<(String, RpcConfig), RpcClient>>,
+}
+
+impl ClientPool {
+    /// Client of the endpoint, created on the first use
+    pub fn get(&self, endpoint: &str, config: &RpcConfig) -> RpcClient {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients
+            .entry((endpoint.to_string(), config.clone()))
+            .or_insert_with(|| RpcClient::new(endpoint, config))
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+static POOL: OnceLock<ClientPool> = OnceLock::new();
+
+impl RpcClient {
+    /// Client of the endpoint from the pool shared by the whole process
+    pub fn shared(endpoint: &str, config: &RpcConfig) -> Self {
+        POOL.get_or_init(ClientPool::default).get(endpoint, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use serde_json::json;
+
+    #[test]
+    pub fn it_serializes_request() {
+        let rq = RpcRequest {
+            jsonrpc: "2.0",
+            method: "eth_getBlockByNumber",
+            params: json!(["0x10", false]),
+            id: 7,
+        };
+        assert_eq!(
+            serde_json::to_string(&rq).unwrap(),
+            r#"{"jsonrpc":"2.0","method":"eth_getBlockByNumber","params":["0x10",false],"id":7}"#
+        );
+    }
+
+    #[test]
+    pub fn it_matches_response_ids() {
+        assert!(is_same_id(7, &json!(7)));
+        assert!(is_same_id(7, &json!("7")));
+        assert!(!is_same_id(7, &json!(8)));
+        assert!(!is_same_id(7, &json!(null)));
+    }
+
+    #[test]
+    pub fn it_skips_notifications() {
+        assert!(is_notification(
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0x1","result":{}}}"#
+        ));
+        assert!(!is_notification(
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x10"}"#
+        ));
+    }
+
+    #[test]
+    pub fn it_decodes_results() {
+        let response: RawResponse =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":"3","result":"0x10"}"#).unwrap();
+        let out: ethereum_types::U64 = decode("eth_blockNumber", response.into_result()).unwrap();
+        assert_eq!(out.as_u64(), 16);
+
+        let response: RawResponse = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":4,"error":{"code":-32601,"message":"method not found"}}"#,
+        )
+        .unwrap();
+        let out: Result<ethereum_types::U64, RpcError> =
+            decode("eth_blockNumber", response.into_result());
+        assert!(out.unwrap_err().is_unsupported_method());
+
+        let out: Result<ethereum_types::U64, RpcError> =
+            decode("eth_blockNumber", Ok(json!({"a": 1})));
+        assert!(matches!(out, Err(RpcError::Decode(_))));
+    }
+
+    #[test]
+    pub fn it_detects_unsupported_method() {
+        let e = RpcError::rpc(
+            -32601,
+            "the method parity_chainStatus does not exist/is not available".to_owned(),
+        );
+        assert!(e.is_unsupported_method());
+        let e = RpcError::rpc(-32000, "Method not supported".to_owned());
+        assert!(e.is_unsupported_method());
+        let e = RpcError::rpc(-32000, "header not found".to_owned());
+        assert!(!e.is_unsupported_method());
+        assert!(!RpcError::Transport("timed out".to_owned()).is_unsupported_method());
+    }
+
+    #[test]
+    pub fn it_reports_http_status() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32005,"message":"limit exceeded"}}"#;
+        assert_eq!(error_body(429, body.to_owned()), Ok(body.to_owned()));
+        let out = error_body(502, "<html>502 Bad Gateway</html>".to_owned());
+        assert_eq!(
+            out,
+            Err(RpcError::HttpStatus {
+                status: 502,
+                body: "<html>502 Bad Gateway</html>".to_owned()
+            })
+        );
+        let out = error_body(503, "x".repeat(1000)).unwrap_err();
+        assert_eq!(
+            out.to_string().len(),
+            "HTTP status 503: ".len() + MAX_ERROR_BODY
+        );
+    }
+
+    #[test]
+    pub fn it_averages_latest_latency() {
+        let mut samples = Samples::default();
+        assert_eq!(samples.average(), Duration::default());
+        for ms in 1..=LATENCY_WINDOW as u64 + 10 {
+            samples.add(Duration::from_millis(ms));
+        }
+        let summary = samples.summary();
+        assert_eq!(summary.calls, LATENCY_WINDOW as u64 + 10);
+        assert_eq!(summary.last_ms, 30.0);
+        // average of 11..=30
+        assert_eq!(summary.avg_ms, 20.5);
+        assert_eq!(millis(Duration::from_micros(1234)), 1.23);
+    }
+
+    #[test]
+    pub fn it_computes_backoff() {
+        let policy = RetryPolicy {
+            count: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            on: vec![RetryOn::Transport, RetryOn::Rpc],
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+        assert_eq!(policy.delay(100), Duration::from_millis(500));
+        assert!(policy.is_retryable(&RpcError::Transport("reset".to_owned())));
+        assert!(!policy.is_retryable(&RpcError::Timeout("timed out".to_owned())));
+        assert!(policy.is_retryable(&RpcError::rpc(-32005, "limit exceeded".to_owned())));
+        assert!(!policy.is_retryable(&RpcError::rpc(-32601, "method not found".to_owned())));
+        assert!(!policy.is_retryable(&RpcError::HttpStatus {
+            status: 502,
+            body: "Bad Gateway".to_owned()
+        }));
+    }
+
+    /// Transport that fails the given number of times before responding
+    #[derive(Debug)]
+    struct Flaky {
+        failures: std::sync::Mutex<u32>,
+        error: RpcError,
+    }
+
+    #[async_trait]
+    impl Transport for Flaky {
+        async fn send(&self, payload: &str, _timeout: Duration) -> Result<String, RpcError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(self.error.clone());
+            }
+            let rq: serde_json::Value = serde_json::from_str(payload).unwrap();
+            Ok(json!({"jsonrpc": "2.0", "id": rq["id"], "result": "0x10"}).to_string())
+        }
+    }
+
+    fn flaky_client(failures: u32, error: RpcError, config: RpcConfig) -> RpcClient {
+        RpcClient {
+            endpoint: "flaky".to_owned(),
+            config,
+            transport: Arc::new(Flaky {
+                failures: std::sync::Mutex::new(failures),
+                error,
+            }),
+            latency: Arc::default(),
+            until: None,
+        }
+    }
+
+    #[test]
+    pub fn it_retries_failed_calls() {
+        let config = RpcConfig {
+            retry: RetryPolicy {
+                backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+            ..RpcConfig::default()
+        };
+        let reset = RpcError::Transport("connection reset".to_owned());
+        let client = flaky_client(2, reset.clone(), config.clone());
+        let out: ethereum_types::U64 = task::block_on(client.call("eth_blockNumber", ())).unwrap();
+        assert_eq!(out.as_u64(), 16);
+        // retries are measured as the single call
+        assert_eq!(client.latency()["eth_blockNumber"].calls, 1);
+        let measured = client.measured();
+        assert!(measured.latency().is_empty());
+        let _: ethereum_types::U64 = task::block_on(measured.call("eth_blockNumber", ())).unwrap();
+        assert_eq!(measured.latency()["eth_blockNumber"].calls, 1);
+        assert_eq!(client.latency()["eth_blockNumber"].calls, 1);
+
+        let client = flaky_client(3, reset.clone(), config.clone());
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert_eq!(out.unwrap_err(), reset);
+
+        let timeout = RpcError::Timeout("timed out".to_owned());
+        let client = flaky_client(1, timeout.clone(), config.clone());
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert_eq!(out.unwrap_err(), timeout);
+
+        // no time is left for the retry
+        let config = RpcConfig {
+            deadline: Some(Duration::from_millis(1)),
+            retry: RetryPolicy {
+                backoff: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            },
+            ..RpcConfig::default()
+        };
+        let client = flaky_client(1, reset.clone(), config);
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert_eq!(out.unwrap_err(), reset);
+    }
+
+    /// Node answering batches in reverse order, without the response to `eth_chainId`,
+    /// or with a single error when batches are not supported
+    #[derive(Debug)]
+    struct Shuffler {
+        batches: bool,
+    }
+
+    #[async_trait]
+    impl Transport for Shuffler {
+        async fn send(&self, payload: &str, _timeout: Duration) -> Result<String, RpcError> {
+            if !self.batches {
+                let error = json!({"code": -32600, "message": "batch requests are not supported"});
+                return Ok(json!({"jsonrpc": "2.0", "id": null, "error": error}).to_string());
+            }
+            let requests: Vec<serde_json::Value> = serde_json::from_str(payload).unwrap();
+            let responses: Vec<serde_json::Value> = requests
+                .iter()
+                .rev()
+                .filter(|rq| rq["method"] != "eth_chainId")
+                .map(|rq| match rq["method"].as_str() {
+                    Some("eth_blockNumber") => {
+                        json!({"jsonrpc": "2.0", "id": rq["id"], "result": "0x10"})
+                    }
+                    _ => {
+                        let error = json!({"code": -32601, "message": "method not found"});
+                        // some nodes return numeric ids as strings
+                        json!({"jsonrpc": "2.0", "id": rq["id"].to_string(), "error": error})
+                    }
+                })
+                .collect();
+            Ok(serde_json::to_string(&responses).unwrap())
+        }
+    }
+
+    #[test]
+    pub fn it_matches_batch_responses_by_id() {
+        let transport = Arc::new(Shuffler { batches: true });
+        let client = RpcClient::with_transport("shuffler", &RpcConfig::default(), transport);
+        let calls = [
+            ("eth_blockNumber", json!([])),
+            ("parity_chainStatus", json!([])),
+            ("eth_chainId", json!([])),
+            ("eth_blockNumber", json!([])),
+        ];
+        let results = task::block_on(client.batch(&calls)).unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], Ok(json!("0x10")));
+        assert!(results[1].as_ref().unwrap_err().is_unsupported_method());
+        assert!(matches!(&results[2], Err(RpcError::Decode(x)) if x.contains("no response")));
+        assert_eq!(results[3], Ok(json!("0x10")));
+    }
+
+    #[test]
+    pub fn it_reports_rejected_batches() {
+        let transport = Arc::new(Shuffler { batches: false });
+        let client = RpcClient::with_transport("shuffler", &RpcConfig::default(), transport);
+        let out = task::block_on(client.batch(&[("eth_blockNumber", json!([]))]));
+        assert_eq!(
+            out.unwrap_err(),
+            RpcError::BatchRejected("batch requests are not supported".to_owned())
+        );
+    }
+
+    /// Transport of the endpoint that never responds, waiting for the whole timeout
+    #[derive(Debug, Default)]
+    struct Hung {
+        timeouts: std::sync::Mutex<Vec<Duration>>,
+    }
+
+    #[async_trait]
+    impl Transport for Arc<Hung> {
+        async fn send(&self, _payload: &str, timeout: Duration) -> Result<String, RpcError> {
+            self.timeouts.lock().unwrap().push(timeout);
+            task::sleep(timeout).await;
+            Err(RpcError::Timeout("timed out reading response".to_owned()))
+        }
+    }
+
+    #[test]
+    pub fn it_limits_attempts_by_deadline() {
+        let hung = Arc::new(Hung::default());
+        let config = RpcConfig {
+            read_timeout: Duration::from_millis(100),
+            deadline: Some(Duration::from_millis(250)),
+            retry: RetryPolicy {
+                count: 10,
+                backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+                on: vec![RetryOn::Timeout],
+            },
+            ..RpcConfig::default()
+        };
+        let client = RpcClient::with_transport("hung", &config, Arc::new(hung.clone()));
+        let started = Instant::now();
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert!(matches!(out, Err(RpcError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_millis(300));
+        // attempts get the read timeout, the last one only what is left of the deadline
+        let timeouts = hung.timeouts.lock().unwrap();
+        assert_eq!(timeouts.len(), 3);
+        assert_eq!(timeouts[0], Duration::from_millis(100));
+        assert!(timeouts[2] < Duration::from_millis(50));
+    }
+
+    #[test]
+    pub fn it_stops_calls_at_client_end() {
+        let hung = Arc::new(Hung::default());
+        let config = RpcConfig {
+            read_timeout: Duration::from_millis(500),
+            deadline: None,
+            ..RpcConfig::default()
+        };
+        let until = Instant::now() + Duration::from_millis(100);
+        let client =
+            RpcClient::with_transport("hung", &config, Arc::new(hung.clone())).until(Some(until));
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert!(matches!(out, Err(RpcError::Timeout(_))));
+        assert!(hung.timeouts.lock().unwrap()[0] <= Duration::from_millis(100));
+        // no time is left for the next calls
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(client.time_left(), Some(Duration::ZERO));
+        let out: Result<ethereum_types::U64, RpcError> =
+            task::block_on(client.call("eth_blockNumber", ()));
+        assert!(matches!(out, Err(RpcError::Timeout(_))));
+        assert_eq!(hung.timeouts.lock().unwrap().len(), 1);
+    }
+
+    /// Local JSON-RPC server with keep-alive, answering `0x10` to every call.
+    /// Returns its address and the counter of accepted connections
+    fn mock_server() -> (String, Arc<AtomicU64>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU64::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::Relaxed);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut writer = stream;
+                    loop {
+                        let mut length = 0;
+                        loop {
+                            let mut line = String::new();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let lower = line.to_lowercase();
+                            if let Some(x) = lower.strip_prefix("content-length:") {
+                                length = x.trim().parse().unwrap();
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                        }
+                        let mut body = vec![0; length];
+                        reader.read_exact(&mut body).unwrap();
+                        let rq: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                        let out = json!({"jsonrpc": "2.0", "id": rq["id"], "result": "0x10"});
+                        let out = out.to_string();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            out.len(),
+                            out
+                        );
+                        if writer.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, connections)
+    }
+
+    #[test]
+    pub fn it_reuses_pooled_connections() {
+        let (addr, connections) = mock_server();
+        let pool = ClientPool::default();
+        let config = RpcConfig::default();
+        for _ in 0..10 {
+            let client = pool.get(&addr, &config);
+            let out: ethereum_types::U64 =
+                task::block_on(client.call("eth_blockNumber", ())).unwrap();
+            assert_eq!(out.as_u64(), 16);
+        }
+        assert_eq!(pool.len(), 1);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_pooled_clients`
+    #[test]
+    #[ignore]
+    pub fn bench_pooled_clients() {
+        const CALLS: u32 = 2000;
+        let (addr, connections) = mock_server();
+        let config = RpcConfig::default();
+
+        let started = Instant::now();
+        for _ in 0..CALLS {
+            let client = RpcClient::new(&addr, &config);
+            let _: ethereum_types::U64 =
+                task::block_on(client.call("eth_blockNumber", ())).unwrap();
+        }
+        let fresh = started.elapsed();
+        let fresh_connections = connections.swap(0, Ordering::Relaxed);
+
+        let pool = ClientPool::default();
+        let started = Instant::now();
+        for _ in 0..CALLS {
+            let client = pool.get(&addr, &config);
+            let _: ethereum_types::U64 =
+                task::block_on(client.call("eth_blockNumber", ())).unwrap();
+        }
+        let pooled = started.elapsed();
+        let pooled_connections = connections.load(Ordering::Relaxed);
+
+        println!(
+            "{} calls: new client {:?} ({:?}/call, {} connections), pooled {:?} ({:?}/call, {} connections)",
+            CALLS,
+            fresh,
+            fresh / CALLS,
+            fresh_connections,
+            pooled,
+            pooled / CALLS,
+            pooled_connections
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use ethabi::param_type::Reader;
+use ethabi::ParamType;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::OnceLock;
+
+/// Signatures of the common methods, that are known without the database file
+const BUNDLED: &[&str] = &[
+    // ERC-20
+    "transfer(address,uint256)",
+    "transferFrom(address,address,uint256)",
+    "approve(address,uint256)",
+    "increaseAllowance(address,uint256)",
+    "decreaseAllowance(address,uint256)",
+    "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)",
+    // ERC-721 and ERC-1155
+    "safeTransferFrom(address,address,uint256)",
+    "safeTransferFrom(address,address,uint256,bytes)",
+    "setApprovalForAll(address,bool)",
+    "safeTransferFrom(address,address,uint256,uint256,bytes)",
+    "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)",
+    "mint(address,uint256)",
+    "burn(uint256)",
+    // WETH
+    "deposit()",
+    "withdraw(uint256)",
+    // Uniswap V2 router
+    "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+    "swapTokensForExactTokens(uint256,uint256,address[],address,uint256)",
+    "swapExactETHForTokens(uint256,address[],address,uint256)",
+    "swapETHForExactTokens(uint256,address[],address,uint256)",
+    "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+    "swapTokensForExactETH(uint256,uint256,address[],address,uint256)",
+    "swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)",
+    "swapExactETHForTokensSupportingFeeOnTransferTokens(uint256,address[],address,uint256)",
+    "swapExactTokensForETHSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)",
+    "addLiquidity(address,address,uint256,uint256,uint256,uint256,address,uint256)",
+    "addLiquidityETH(address,uint256,uint256,uint256,address,uint256)",
+    "removeLiquidity(address,address,uint256,uint256,uint256,address,uint256)",
+    "removeLiquidityETH(address,uint256,uint256,uint256,address,uint256)",
+    // Uniswap V3 router and universal router
+    "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+    "exactInput((bytes,address,uint256,uint256,uint256))",
+    "exactOutputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+    "exactOutput((bytes,address,uint256,uint256,uint256))",
+    "multicall(bytes[])",
+    "multicall(uint256,bytes[])",
+    "execute(bytes,bytes[])",
+    "execute(bytes,bytes[],uint256)",
+    // multisig and account abstraction
+    "execTransaction(address,uint256,bytes,uint8,uint256,uint256,uint256,address,address,bytes)",
+    "handleOps((address,uint256,bytes,bytes,uint256,uint256,uint256,uint256,uint256,bytes,bytes)[],address)",
+    // misc
+    "claim()",
+    "stake(uint256)",
+    "unstake(uint256)",
+    "withdraw()",
+];
+
+/// Method signature of the selector database
+#[derive(Debug, Clone, PartialEq)]
+struct Signature {
+    text: String,
+    params: Vec<ParamType>,
+}
+
+impl Signature {
+    /// Parses the signature, i.e. `transfer(address,uint256)`, with its selector
+    fn parse(signature: &str) -> Result<([u8; 4], Self), String> {
+        let text: String = signature.chars().filter(|x| !x.is_whitespace()).collect();
+        let invalid = || format!("invalid method signature {}", signature);
+        let (name, params) = text.split_once('(').ok_or_else(invalid)?;
+        if name.is_empty() || !params.ends_with(')') {
+            return Err(invalid());
+        }
+        let params = match &params[..params.len() - 1] {
+            "" => vec![],
+            x => match Reader::read(&format!("({})", x)).map_err(|_| invalid())? {
+                ParamType::Tuple(x) => x,
+                x => vec![x],
+            },
+        };
+        let selector = ethabi::short_signature(name, &params);
+        Ok((selector, Self { text, params }))
+    }
+
+    /// How well the call data fits the parameters: 2 when it has exactly the size of the
+    /// static parameters, 1 when it can be decoded, 0 when it can't
+    fn score(&self, data: &[u8]) -> u8 {
+        if ethabi::decode(&self.params, data).is_err() {
+            return 0;
+        }
+        let is_static = self.params.iter().all(|x| !x.is_dynamic());
+        if is_static && data.len() == 32 * self.head_words() {
+            return 2;
+        }
+        1
+    }
+
+    /// Number of 32-byte words of the static parameters
+    fn head_words(&self) -> usize {
+        fn words(kind: &ParamType) -> usize {
+            match kind {
+                ParamType::FixedArray(x, n) => words(x) * n,
+                ParamType::Tuple(x) => x.iter().map(words).sum(),
+                _ => 1,
+            }
+        }
+        self.params.iter().map(words).sum()
+    }
+}
+
+/// Numbers of the signatures added from the file and of the invalid ones skipped
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Loaded {
+    pub signatures: usize,
+    pub skipped: usize,
+}
+
+/// Database of method signatures by their 4-byte selectors.
+/// Several signatures may share the selector, the one that fits the call data is chosen
+#[derive(Debug, Clone, Default)]
+pub struct SignatureDb {
+    signatures: HashMap<[u8; 4], Vec<Signature>>,
+}
+
+impl SignatureDb {
+    /// Database with the signatures of the common methods
+    pub fn bundled() -> Self {
+        let mut db = Self::default();
+        for signature in BUNDLED {
+            db.add(signature).expect("bundled method signature");
+        }
+        db
+    }
+
+    /// Adds the signature, unless it is already known
+    pub fn add(&mut self, signature: &str) -> Result<(), String> {
+        let (selector, signature) = Signature::parse(signature)?;
+        let known = self.signatures.entry(selector).or_default();
+        if !known.contains(&signature) {
+            known.push(signature);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.values().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds signatures from the text file, one per line. Lines may start with the selector,
+    /// separated with a space or comma, as in the exports of 4byte.directory.
+    /// Empty lines and lines starting with `#` are skipped, so are invalid signatures,
+    /// which are common in the exports
+    pub fn load(&mut self, reader: impl BufRead) -> anyhow::Result<Loaded> {
+        let mut loaded = Loaded::default();
+        let mut first_error = None;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let signature = match line.split_once([' ', ',', '\t']) {
+                Some((selector, rest)) if selector.starts_with("0x") => rest.trim(),
+                _ => line,
+            };
+            match self.add(signature) {
+                Ok(_) => loaded.signatures += 1,
+                Err(e) => {
+                    tracing::debug!("line {}: {}", n + 1, e);
+                    first_error.get_or_insert(format!("line {}: {}", n + 1, e));
+                    loaded.skipped += 1;
+                }
+            }
+        }
+        if let Some(err) = first_error {
+            tracing::warn!("{} invalid signatures are skipped, {}", loaded.skipped, err);
+        }
+        Ok(loaded)
+    }
+
+    /// Bundled signatures extended with the ones from the file
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .map_err(|e| anyhow::anyhow!("failed to open signatures file {}: {}", path, e))?;
+        let mut db = Self::bundled();
+        db.load(BufReader::new(file))
+            .map_err(|e| anyhow::anyhow!("failed to read signatures file {}: {}", path, e))?;
+        Ok(db)
+    }
+
+    /// Signature of the method called with the input, the one that fits the call data best.
+    /// Of equally fitting ones, the first added is chosen, none when the call data fits none of them
+    pub fn lookup(&self, input: &[u8]) -> Option<&str> {
+        if input.len() < 4 {
+            return None;
+        }
+        let mut selector = [0u8; 4];
+        selector.copy_from_slice(&input[..4]);
+        let data = &input[4..];
+        let mut best: Option<(&Signature, u8)> = None;
+        for signature in self.signatures.get(&selector)? {
+            let score = signature.score(data);
+            // the signature that can't decode the call data is not the called method
+            if score > 0 && best.map(|(_, x)| score > x).unwrap_or(true) {
+                best = Some((signature, score));
+            }
+        }
+        best.map(|(x, _)| x.text.as_str())
+    }
+}
+
+static DB: OnceLock<SignatureDb> = OnceLock::new();
+
+/// Sets up the signature database shared by the whole process.
+/// Returns false if it is already in use
+pub fn configure(db: SignatureDb) -> bool {
+    DB.set(db).is_ok()
+}
+
+/// Signature database shared by the whole process, the bundled one unless it was configured
+pub fn shared() -> &'static SignatureDb {
+    DB.get_or_init(SignatureDb::bundled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    const TRANSFER: [u8; 68] = hex!("a9059cbb000000000000000000000000000000000000000000000000000000000000dead0000000000000000000000000000000000000000000000000de0b6b3a7640000");
+
+    #[test]
+    pub fn it_parses_signatures() {
+        let (selector, signature) = Signature::parse("transfer(address, uint256)").unwrap();
+        assert_eq!(selector, hex!("a9059cbb"));
+        assert_eq!(signature.text, "transfer(address,uint256)");
+        let (selector, _) = Signature::parse("deposit()").unwrap();
+        assert_eq!(selector, hex!("d0e30db0"));
+        let (selector, signature) = Signature::parse(
+            "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))",
+        )
+        .unwrap();
+        assert_eq!(selector, hex!("414bf389"));
+        assert_eq!(signature.head_words(), 8);
+        assert!(Signature::parse("transfer").is_err());
+        assert!(Signature::parse("transfer(address,uint257x)").is_err());
+    }
+
+    #[test]
+    pub fn it_looks_up_bundled() {
+        let db = SignatureDb::bundled();
+        assert_eq!(db.lookup(&TRANSFER), Some("transfer(address,uint256)"));
+        assert_eq!(db.lookup(&hex!("d0e30db0")), Some("deposit()"));
+        assert_eq!(db.lookup(&hex!("12345678")), None);
+        assert_eq!(db.lookup(&hex!("a905")), None);
+    }
+
+    #[test]
+    pub fn it_prefers_fitting_signature() {
+        // selector collision of 4byte.directory: the first one doesn't fit the call data
+        let mut db = SignatureDb::default();
+        let input = "0xa9059cbb many_msg_babbage(bytes1)\n# comment\n\n0xa9059cbb,transfer(address,uint256)\nwithdraw(uint256)\n";
+        let loaded = db.load(input.as_bytes()).unwrap();
+        assert_eq!(loaded.signatures, 3);
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.lookup(&TRANSFER), Some("transfer(address,uint256)"));
+        // call data too short for both, none of them is the called method
+        assert_eq!(db.lookup(&TRANSFER[..4]), None);
+    }
+
+    #[test]
+    pub fn it_skips_invalid_signatures() {
+        let mut db = SignatureDb::default();
+        let input = "0xa9059cbb nonsense
+0x12345678 f(uint257x)
+0xd0e30db0 deposit()
+";
+        let loaded = db.load(input.as_bytes()).unwrap();
+        assert_eq!(
+            loaded,
+            Loaded {
+                signatures: 1,
+                skipped: 2
+            }
+        );
+        assert_eq!(db.lookup(&hex!("d0e30db0")), Some("deposit()"));
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+use std::fmt;
+
+/// Boolean expression over network tags, i.e. `(rsk or avalanche) and not testnet`.
+/// Comma is a shorthand for `and` and leading `-` for `not`, so `rsk,-testnet` still works
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpr {
+    /// Empty expression, matches any network
+    Any,
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Vec<TagExpr>),
+    Or(Vec<TagExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagExprError {
+    pub message: String,
+    pub source: String,
+    /// Character offset of the offending token in the source
+    pub position: usize,
+}
+
+impl fmt::Display for TagExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} at position {}", self.message, self.position + 1)?;
+        writeln!(f, "  {}", self.source)?;
+        write!(f, "  {}^", " ".repeat(self.position))
+    }
+}
+
+impl std::error::Error for TagExprError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Minus,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open => write!(f, "`(`"),
+            Self::Close => write!(f, "`)`"),
+            Self::Comma => write!(f, "`,`"),
+            Self::Minus => write!(f, "`-`"),
+            Self::And => write!(f, "`and`"),
+            Self::Or => write!(f, "`or`"),
+            Self::Not => write!(f, "`not`"),
+            Self::Word(w) => write!(f, "`{}`", w),
+            Self::Quoted(w) => write!(f, "`\"{}\"`", w),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, TagExprError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let start = i;
+        match ch {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => out.push((Token::Open, start)),
+            ')' => out.push((Token::Close, start)),
+            ',' => out.push((Token::Comma, start)),
+            '-' => out.push((Token::Minus, start)),
+            '"' => {
+                let mut word = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(TagExprError {
+                        message: "unterminated quoted tag".to_owned(),
+                        source: src.to_owned(),
+                        position: start,
+                    });
+                }
+                out.push((Token::Quoted(word), start));
+            }
+            _ => {
+                let mut word = String::new();
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], '(' | ')' | ',' | '"')
+                {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                let token = match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                };
+                out.push((token, start));
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn error(&self, message: String) -> TagExprError {
+        let position = match self.tokens.get(self.pos) {
+            Some((_, p)) => *p,
+            None => self.src.chars().count(),
+        };
+        TagExprError {
+            message,
+            source: self.src.to_owned(),
+            position,
+        }
+    }
+
+    fn unexpected(&self) -> TagExprError {
+        match self.peek() {
+            Some(t) => self.error(format!("unexpected {}", t)),
+            None => self.error("unexpected end of expression".to_owned()),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<TagExpr, TagExprError> {
+        let mut items = vec![self.parse_and()?];
+        while let Some(Token::Or) = self.peek() {
+            self.pos += 1;
+            items.push(self.parse_and()?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            TagExpr::Or(items)
+        })
+    }
+
+    /// Skips commas, as empty items of the comma list are ignored
+    fn skip_commas(&mut self) {
+        while let Some(Token::Comma) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<TagExpr, TagExprError> {
+        self.skip_commas();
+        let mut items = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.pos += 1;
+                    items.push(self.parse_unary()?);
+                }
+                Some(Token::Comma) => {
+                    self.skip_commas();
+                    match self.peek() {
+                        None | Some(Token::Close) | Some(Token::Or) => break,
+                        _ => items.push(self.parse_unary()?),
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            TagExpr::And(items)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<TagExpr, TagExprError> {
+        match self.peek() {
+            Some(Token::Not) | Some(Token::Minus) => {
+                self.pos += 1;
+                Ok(TagExpr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let inner = self.parse_or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    Some(t) => Err(self.error(format!("expected `)`, found {}", t))),
+                    None => Err(self.error("expected `)`, found end of expression".to_owned())),
+                }
+            }
+            Some(Token::Quoted(w)) => {
+                let tag = w.clone();
+                self.pos += 1;
+                Ok(TagExpr::Tag(tag))
+            }
+            Some(Token::Word(_)) => {
+                // consecutive words form a single tag, as file tags may contain spaces
+                let mut words = vec![];
+                while let Some(Token::Word(w)) = self.peek() {
+                    words.push(w.clone());
+                    self.pos += 1;
+                }
+                Ok(TagExpr::Tag(words.join(" ")))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+impl TagExpr {
+    pub fn parse(src: &str) -> Result<Self, TagExprError> {
+        let tokens = tokenize(src)?;
+        if tokens.iter().all(|(t, _)| *t == Token::Comma) {
+            return Ok(Self::Any);
+        }
+        let mut parser = Parser {
+            src,
+            tokens,
+            pos: 0,
+        };
+        let expr = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected());
+        }
+        Ok(expr)
+    }
+
+    /// Tags that a network must have to match the expression,
+    /// i.e. the tags of the single network given with `--network`
+    pub fn required_tags(&self) -> HashSet<String> {
+        match self {
+            Self::Any | Self::Not(_) => HashSet::new(),
+            Self::Tag(t) => [t.clone()].into_iter().collect(),
+            Self::And(items) => items.iter().flat_map(|x| x.required_tags()).collect(),
+            Self::Or(items) => {
+                let mut sets = items.iter().map(|x| x.required_tags());
+                let first = sets.next().unwrap_or_default();
+                sets.fold(first, |all, x| all.intersection(&x).cloned().collect())
+            }
+        }
+    }
+
+    pub fn matches(&self, tags: &HashSet<String>) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tag(t) => tags.contains(t),
+            Self::Not(x) => !x.matches(tags),
+            Self::And(items) => items.iter().all(|x| x.matches(tags)),
+            Self::Or(items) => items.iter().any(|x| x.matches(tags)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> HashSet<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    pub fn it_parses_comma_shorthand() {
+        let expr = TagExpr::parse("rsk,-testnet").unwrap();
+        assert_eq!(
+            expr,
+            TagExpr::And(vec![
+                TagExpr::Tag("rsk".to_owned()),
+                TagExpr::Not(Box::new(TagExpr::Tag("testnet".to_owned()))),
+            ])
+        );
+        assert!(expr.matches(&tags(&["rsk", "nosync"])));
+        assert!(!expr.matches(&tags(&["rsk", "testnet"])));
+    }
+
+    #[test]
+    pub fn it_parses_empty_as_any() {
+        assert_eq!(TagExpr::parse("  ").unwrap(), TagExpr::Any);
+        assert!(TagExpr::Any.matches(&HashSet::new()));
+    }
+
+    #[test]
+    pub fn it_respects_precedence() {
+        let expr = TagExpr::parse("(rsk or avalanche) and not testnet").unwrap();
+        assert!(expr.matches(&tags(&["rsk"])));
+        assert!(expr.matches(&tags(&["avalanche", "nosync"])));
+        assert!(!expr.matches(&tags(&["avalanche", "testnet"])));
+        assert!(!expr.matches(&tags(&["arbitrum"])));
+
+        // `and` binds tighter than `or`
+        let expr = TagExpr::parse("rsk or avalanche and testnet").unwrap();
+        assert!(expr.matches(&tags(&["rsk"])));
+        assert!(!expr.matches(&tags(&["avalanche"])));
+    }
+
+    #[test]
+    pub fn it_reads_tags_with_spaces() {
+        let expr = TagExpr::parse("third, no test").unwrap();
+        assert!(expr.matches(&tags(&["third", "no test"])));
+        let expr = TagExpr::parse(r#""or" or -"not""#).unwrap();
+        assert!(expr.matches(&tags(&["or", "not"])));
+        assert!(expr.matches(&tags(&[])));
+        assert!(!expr.matches(&tags(&["not"])));
+    }
+
+    #[test]
+    pub fn it_points_at_offending_token() {
+        let err = TagExpr::parse("rsk or (avalanche))").unwrap_err();
+        assert_eq!(err.position, 18);
+        assert_eq!(err.message, "unexpected `)`");
+
+        let err = TagExpr::parse("rsk and or testnet").unwrap_err();
+        assert_eq!(err.position, 8);
+        assert_eq!(err.message, "unexpected `or`");
+
+        let err = TagExpr::parse("(rsk or avalanche").unwrap_err();
+        assert_eq!(err.position, 17);
+        assert_eq!(err.message, "expected `)`, found end of expression");
+
+        let err = TagExpr::parse("rsk and").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of expression at position 8\n  rsk and\n         ^"
+        );
+    }
+
+    #[test]
+    pub fn it_skips_empty_comma_items() {
+        let expected = TagExpr::parse("a,b").unwrap();
+        assert_eq!(TagExpr::parse("a,,b").unwrap(), expected);
+        assert_eq!(TagExpr::parse(",a, b,").unwrap(), expected);
+        assert_eq!(
+            TagExpr::parse("rsk,").unwrap(),
+            TagExpr::Tag("rsk".to_owned())
+        );
+        assert_eq!(TagExpr::parse(",").unwrap(), TagExpr::Any);
+        assert!(TagExpr::parse("(a,) or b").unwrap().matches(&tags(&["b"])));
+    }
+
+    #[test]
+    pub fn it_lists_required_tags() {
+        let expr = TagExpr::parse("nosync,-testnet,nogaps").unwrap();
+        assert_eq!(expr.required_tags(), tags(&["nosync", "nogaps"]));
+        let expr = TagExpr::parse("rsk and (nosync or nosync, nogaps)").unwrap();
+        assert_eq!(expr.required_tags(), tags(&["rsk", "nosync"]));
+        assert!(TagExpr::Any.required_tags().is_empty());
+    }
+}
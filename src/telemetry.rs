@@ -57,19 +57,10 @@ impl TraceMiddleware {
             None => "".to_owned(),
         };
 
-        let geo = vec![
-            match ctx.header("x-country-code") {
-                Some(hv) => Some(hv.to_string()),
-                None => None,
-            },
-            match ctx.header("x-city-en-name") {
-                Some(hv) => Some(hv.to_string()),
-                None => None,
-            },
-            match ctx.header("x-location-accuracy") {
-                Some(hv) => Some(hv.to_string()),
-                None => None,
-            },
+        let geo = [
+            ctx.header("x-country-code").map(|hv| hv.to_string()),
+            ctx.header("x-city-en-name").map(|hv| hv.to_string()),
+            ctx.header("x-location-accuracy").map(|hv| hv.to_string()),
         ]
         .iter()
         .flatten()
@@ -102,7 +93,7 @@ impl TraceMiddleware {
             response
         }
         .instrument(
-            if geo.len() > 0 {
+            if !geo.is_empty() {
                 info_span!("Request", rq = %rqid, m = %method, u = %path, ip = %ip, agent = %ua, geo = %geo)
             } else {
                 info_span!("Request", rq = %rqid, m = %method, u = %path, ip = %ip, agent = %ua)
@@ -0,0 +1,284 @@
+use crate::rpc::{io_error, is_notification, timed_out, RpcClient, RpcConfig, RpcError, Transport};
+use async_std::future;
+use async_std::net::TcpStream;
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::{self, Message};
+use async_tungstenite::WebSocketStream;
+use ethereum_types::U64;
+use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// Byte stream of the connection, plain TCP or TLS
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type Socket = WebSocketStream<Box<dyn Stream>>;
+
+/// Whether the endpoint should be reached over WebSocket
+pub fn is_ws(endpoint: &str) -> bool {
+    let endpoint = endpoint.to_lowercase();
+    endpoint.starts_with("ws://") || endpoint.starts_with("wss://")
+}
+
+/// Open WebSocket connection
+struct Connection {
+    socket: Socket,
+}
+
+impl Connection {
+    /// Connects within the connect timeout, the handshakes are limited by the timeout
+    /// of the attempt
+    async fn open(endpoint: &str, config: &RpcConfig, timeout: Duration) -> Result<Self, RpcError> {
+        let request = endpoint
+            .into_client_request()
+            .map_err(|e| RpcError::Transport(format!("{}: {}", endpoint, e)))?;
+        let uri = request.uri();
+        let tls = uri.scheme_str() == Some("wss");
+        let host = uri
+            .host()
+            .ok_or_else(|| RpcError::Transport(format!("{}: no host", endpoint)))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+        let connect_timeout = config.connect_timeout.min(timeout);
+        let tcp = future::timeout(connect_timeout, TcpStream::connect((host.as_str(), port)))
+            .await
+            .map_err(|_| RpcError::Timeout(format!("{}: connect timed out", endpoint)))?
+            .map_err(io_error)?;
+        let handshake = async {
+            let stream: Box<dyn Stream> = match tls {
+                true => Box::new(
+                    async_tls::TlsConnector::default()
+                        .connect(&host, tcp)
+                        .await
+                        .map_err(io_error)?,
+                ),
+                false => Box::new(tcp),
+            };
+            let (socket, _) = async_tungstenite::client_async(request, stream)
+                .await
+                .map_err(|e| RpcError::Transport(format!("{}: {}", endpoint, e)))?;
+            Ok(Self { socket })
+        };
+        future::timeout(timeout, handshake)
+            .await
+            .map_err(|_| timed_out(timeout))?
+    }
+
+    async fn write(&mut self, payload: &str) -> Result<(), RpcError> {
+        self.socket
+            .send(Message::Text(payload.to_string()))
+            .await
+            .map_err(transport_error)
+    }
+
+    /// Next text message, control frames are handled by the socket
+    async fn read(&mut self) -> Result<String, RpcError> {
+        loop {
+            let message = match self.socket.next().await {
+                Some(x) => x.map_err(transport_error)?,
+                None => return Err(RpcError::Transport("connection closed".to_owned())),
+            };
+            match message {
+                Message::Text(x) => return Ok(x),
+                Message::Binary(x) => {
+                    return String::from_utf8(x).map_err(|e| RpcError::Decode(e.to_string()))
+                }
+                Message::Close(_) => {
+                    return Err(RpcError::Transport("connection closed".to_owned()))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends the request and waits for its response within the timeout
+    async fn exchange(&mut self, payload: &str, timeout: Duration) -> Result<String, RpcError> {
+        let exchange = async {
+            self.write(payload).await?;
+            // requests are sent one at a time, so the next response is the one we wait for
+            loop {
+                let body = self.read().await?;
+                if !is_notification(&body) {
+                    return Ok(body);
+                }
+            }
+        };
+        future::timeout(timeout, exchange)
+            .await
+            .map_err(|_| timed_out(timeout))?
+    }
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection").finish_non_exhaustive()
+    }
+}
+
+fn transport_error(e: tungstenite::Error) -> RpcError {
+    match e {
+        tungstenite::Error::Io(e) => io_error(e),
+        e => RpcError::Transport(e.to_string()),
+    }
+}
+
+/// JSON-RPC over WebSocket. The connection is opened on the first request,
+/// kept for the following ones and reopened after a failure
+#[derive(Debug)]
+pub struct WsTransport {
+    endpoint: String,
+    config: RpcConfig,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl WsTransport {
+    pub fn new(endpoint: &str, config: &RpcConfig) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            config: config.clone(),
+            connection: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for WsTransport {
+    async fn send(&self, payload: &str, timeout: Duration) -> Result<String, RpcError> {
+        let mut guard = self.connection.lock().await;
+        let result = match guard.as_mut() {
+            Some(conn) => conn.exchange(payload, timeout).await,
+            None => {
+                let started = Instant::now();
+                let mut conn = Connection::open(&self.endpoint, &self.config, timeout).await?;
+                let left = timeout.saturating_sub(started.elapsed());
+                let result = conn.exchange(payload, left).await;
+                *guard = Some(conn);
+                result
+            }
+        };
+        if result.is_err() {
+            *guard = None;
+        }
+        result
+    }
+}
+
+/// Heads received from `eth_subscribe("newHeads")` during the observation
+#[derive(Debug, Clone, Default)]
+pub struct NewHeads {
+    /// Block numbers with the time passed since subscription
+    pub heads: Vec<(u64, Duration)>,
+    /// Time passed since the last notification (or subscription) when observation ended
+    pub silence: Duration,
+}
+
+impl NewHeads {
+    /// Longest time without notifications, including the time before the first one
+    /// and after the last one
+    pub fn max_interval(&self) -> Duration {
+        let mut prev = Duration::default();
+        let mut max = self.silence;
+        for (_, at) in &self.heads {
+            max = max.max(*at - prev);
+            prev = *at;
+        }
+        max
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HeadNotification {
+    params: HeadParams,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeadParams {
+    subscription: serde_json::Value,
+    result: Head,
+}
+
+#[derive(Debug, Deserialize)]
+struct Head {
+    number: U64,
+}
+
+/// Subscribes to `newHeads` on the dedicated connection of the client's endpoint and collects
+/// notifications, until `count` of them are received or there was none for `silence`.
+/// Subscription follows the retry policy and the deadlines of the client
+pub async fn watch_new_heads(
+    client: &RpcClient,
+    count: usize,
+    silence: Duration,
+) -> Result<NewHeads, RpcError> {
+    let (payload, id) = client.payload("eth_subscribe", ["newHeads"])?;
+    let payload = &payload;
+    let (mut conn, subscription) = client
+        .measure("eth_subscribe", |timeout| async move {
+            let started = Instant::now();
+            let mut conn = Connection::open(client.endpoint(), client.config(), timeout).await?;
+            let left = timeout.saturating_sub(started.elapsed());
+            let body = conn.exchange(payload, left).await?;
+            let subscription: serde_json::Value = client.response(id, "eth_subscribe", &body)?;
+            Ok((conn, subscription))
+        })
+        .await?;
+    let started = Instant::now();
+    let mut out = NewHeads::default();
+    let mut last = Duration::default();
+    while out.heads.len() < count {
+        let elapsed = started.elapsed();
+        let left = (last + silence).saturating_sub(elapsed);
+        let left = client.time_left().map(|x| x.min(left)).unwrap_or(left);
+        if left.is_zero() {
+            break;
+        }
+        let body = match future::timeout(left, conn.read()).await {
+            Ok(Ok(x)) => x,
+            Ok(Err(RpcError::Timeout(_))) | Err(_) => break,
+            Ok(Err(e)) => return Err(e),
+        };
+        if let Ok(n) = serde_json::from_str::<HeadNotification>(&body) {
+            if n.params.subscription == subscription {
+                last = started.elapsed();
+                out.heads.push((n.params.result.number.as_u64(), last));
+            }
+        }
+    }
+    out.silence = started.elapsed().saturating_sub(last).min(silence);
+    let _ = conn.socket.close(None).await;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_detects_ws_endpoints() {
+        assert!(is_ws("ws://localhost:8546"));
+        assert!(is_ws("WSS://mainnet.example.com/ws"));
+        assert!(!is_ws("https://mainnet.example.com"));
+    }
+
+    #[test]
+    pub fn it_measures_head_intervals() {
+        let s = Duration::from_secs;
+        let heads = NewHeads {
+            heads: vec![(10, s(2)), (11, s(14)), (12, s(26))],
+            silence: s(0),
+        };
+        assert_eq!(heads.max_interval(), s(12));
+        let heads = NewHeads {
+            heads: vec![(10, s(2))],
+            silence: s(30),
+        };
+        assert_eq!(heads.max_interval(), s(30));
+        assert_eq!(NewHeads::default().max_interval(), s(0));
+    }
+}